@@ -0,0 +1,313 @@
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Cooldown {
+    // seconds between two forwarded messages of the same user (to any tag)
+    #[serde(default)]
+    pub per_user: u64,
+    // seconds between two forwarded messages of the tag
+    #[serde(default)]
+    pub per_tag: u64,
+    // forwarded messages per user (to any tag) until the bot is (re)activated
+    #[serde(default)]
+    pub max_per_stream: u32,
+    // seconds in which the same text of a user is dropped (per tag)
+    #[serde(default)]
+    pub duplicate_per_user: u64,
+    // seconds in which the same text of anyone is dropped
+    #[serde(default)]
+    pub duplicate_per_tag: u64,
+}
+
+// the value of the tag, unless it is unset (0)
+fn or_base<T: Default + PartialEq>(value: T, base: T) -> T {
+    if value == T::default() {
+        base
+    } else {
+        value
+    }
+}
+
+impl Cooldown {
+    pub fn is_disabled(&self) -> bool {
+        *self == Cooldown::default()
+    }
+
+    /// The cooldown of a tag, the limits it doesn't set are those of the channel.
+    pub fn merged(&self, channel: &Cooldown) -> Cooldown {
+        Cooldown {
+            per_user: or_base(self.per_user, channel.per_user),
+            per_tag: or_base(self.per_tag, channel.per_tag),
+            max_per_stream: or_base(self.max_per_stream, channel.max_per_stream),
+            duplicate_per_user: or_base(self.duplicate_per_user, channel.duplicate_per_user),
+            duplicate_per_tag: or_base(self.duplicate_per_tag, channel.duplicate_per_tag),
+        }
+    }
+}
+
+// chat clients append these to repeat a message despite the duplicate check of twitch
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{034F}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{FEFF}'
+            | '\u{E0000}'..='\u{E007F}'
+    )
+}
+
+/// Hash of the text ignoring case, whitespace and invisible characters.
+fn text_hash(text: &str) -> u64 {
+    let text = text
+        .chars()
+        .filter(|c| !is_invisible(*c))
+        .collect::<String>()
+        .to_lowercase();
+    let mut hasher = DefaultHasher::new();
+    text.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .hash(&mut hasher);
+    hasher.finish()
+}
+
+// forwarded texts with the time they were forwarded
+type Window = Vec<(Instant, u64)>;
+
+fn in_window(window: Option<&Window>, hash: u64, secs: u64) -> bool {
+    window.is_some_and(|w| {
+        w.iter()
+            .any(|(t, h)| *h == hash && t.elapsed() < Duration::from_secs(secs))
+    })
+}
+
+fn remember(window: &mut Window, hash: u64, secs: u64) {
+    window.retain(|(t, _)| t.elapsed() < Duration::from_secs(secs));
+    window.push((Instant::now(), hash));
+}
+
+/// The in-memory state of the cooldowns, owned by the message loop.
+#[derive(Debug, Default)]
+pub struct Cooldowns {
+    // by channel and user, switching the tag doesn't avoid the limits
+    users: HashMap<(String, String), Instant>,
+    tags: HashMap<(String, String), Instant>,
+    counts: HashMap<(String, String), u32>,
+    tag_counts: HashMap<(String, String), u32>,
+    user_texts: HashMap<(String, String, String), Window>,
+    tag_texts: HashMap<(String, String), Window>,
+}
+
+impl Cooldowns {
+    /// Forgets the limits of the channel, e.g. when a new stream starts.
+    pub fn reset(&mut self, channel: &str) {
+        self.users.retain(|(c, _), _| c != channel);
+        self.tags.retain(|(c, _), _| c != channel);
+        self.counts.retain(|(c, _), _| c != channel);
+        self.tag_counts.retain(|(c, _), _| c != channel);
+        self.user_texts.retain(|(c, _, _), _| c != channel);
+        self.tag_texts.retain(|(c, _), _| c != channel);
+    }
+
+    /// Whether the text was forwarded within the duplicate windows.
+    pub fn is_duplicate(
+        &self,
+        cooldown: &Cooldown,
+        channel: &str,
+        tag: &str,
+        user: &str,
+        text: &str,
+    ) -> bool {
+        let hash = text_hash(text);
+        let user_key = (channel.to_string(), tag.to_string(), user.to_lowercase());
+        let tag_key = (channel.to_string(), tag.to_string());
+        in_window(
+            self.user_texts.get(&user_key),
+            hash,
+            cooldown.duplicate_per_user,
+        ) || in_window(
+            self.tag_texts.get(&tag_key),
+            hash,
+            cooldown.duplicate_per_tag,
+        )
+    }
+
+    /// Whether the limits allow to forward a message of the user to the tag.
+    pub fn allow(&self, cooldown: &Cooldown, channel: &str, tag: &str, user: &str) -> bool {
+        let now = Instant::now();
+        let user_key = (channel.to_string(), user.to_lowercase());
+        let tag_key = (channel.to_string(), tag.to_string());
+        let within = |last: Option<&Instant>, secs: u64| {
+            last.is_some_and(|l| now.duration_since(*l) < Duration::from_secs(secs))
+        };
+        let count = self.counts.get(&user_key).copied().unwrap_or(0);
+        !(within(self.users.get(&user_key), cooldown.per_user)
+            || within(self.tags.get(&tag_key), cooldown.per_tag)
+            || (cooldown.max_per_stream > 0 && count >= cooldown.max_per_stream))
+    }
+
+    /// Records a message posted to the tag, the user is recorded once per message with `record_user`.
+    pub fn record(
+        &mut self,
+        cooldown: &Cooldown,
+        channel: &str,
+        tag: &str,
+        user: &str,
+        text: &str,
+    ) {
+        let user_key = (channel.to_string(), tag.to_string(), user.to_lowercase());
+        let tag_key = (channel.to_string(), tag.to_string());
+        if cooldown.duplicate_per_user > 0 || cooldown.duplicate_per_tag > 0 {
+            let hash = text_hash(text);
+            remember(
+                self.user_texts.entry(user_key.clone()).or_default(),
+                hash,
+                cooldown.duplicate_per_user,
+            );
+            remember(
+                self.tag_texts.entry(tag_key.clone()).or_default(),
+                hash,
+                cooldown.duplicate_per_tag,
+            );
+        }
+        self.tags.insert(tag_key.clone(), Instant::now());
+        *self.tag_counts.entry(tag_key).or_default() += 1;
+    }
+
+    /// Records a message of the user that was posted to at least one tag.
+    pub fn record_user(&mut self, channel: &str, user: &str) {
+        let user_key = (channel.to_string(), user.to_lowercase());
+        self.users.insert(user_key.clone(), Instant::now());
+        *self.counts.entry(user_key).or_default() += 1;
+    }
+
+    /// Forwarded messages of the user (all tags) since the bot was (re)activated.
+    pub fn submissions(&self, channel: &str, user: &str) -> u32 {
+        self.counts
+            .get(&(channel.to_string(), user.to_lowercase()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Forwarded messages of the tag since the bot was (re)activated.
+    pub fn position(&self, channel: &str, tag: &str) -> u32 {
+        self.tag_counts
+            .get(&(channel.to_string(), tag.to_string()))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tag_cooldowns_override_set_limits() {
+        let channel = Cooldown {
+            per_user: 60,
+            max_per_stream: 3,
+            duplicate_per_tag: 600,
+            ..Default::default()
+        };
+        let tag = Cooldown {
+            per_user: 10,
+            per_tag: 30,
+            ..Default::default()
+        };
+        assert_eq!(
+            tag.merged(&channel),
+            Cooldown {
+                per_user: 10,
+                per_tag: 30,
+                max_per_stream: 3,
+                duplicate_per_user: 0,
+                duplicate_per_tag: 600,
+            }
+        );
+        assert_eq!(Cooldown::default().merged(&channel), channel);
+        assert_eq!(tag.merged(&Cooldown::default()), tag);
+    }
+
+    #[test]
+    fn duplicates_ignore_case_whitespace_and_invisible_characters() {
+        assert_eq!(text_hash("#q Hello  world"), text_hash("#q hello world "));
+        assert_eq!(
+            text_hash("#q hello world"),
+            text_hash("#q hello world \u{E0000}")
+        );
+        assert_eq!(
+            text_hash("#q hello\u{200B} world"),
+            text_hash("#q hello world")
+        );
+        assert_ne!(text_hash("#q hello world"), text_hash("#q hello word"));
+
+        let cooldown = Cooldown {
+            duplicate_per_user: 60,
+            ..Default::default()
+        };
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.record(&cooldown, "chan", "#q", "Bob", "#q hello");
+        assert!(cooldowns.is_duplicate(&cooldown, "chan", "#q", "bob", "#Q HELLO \u{034F}"));
+        assert!(!cooldowns.is_duplicate(&cooldown, "chan", "#q", "ann", "#q hello"));
+        assert!(!cooldowns.is_duplicate(&cooldown, "chan", "#other", "bob", "#q hello"));
+        assert!(!cooldowns.is_duplicate(&cooldown, "other", "#q", "bob", "#q hello"));
+    }
+
+    #[test]
+    fn user_limits_apply_to_all_tags() {
+        let cooldown = Cooldown {
+            per_user: 60,
+            ..Default::default()
+        };
+        let mut cooldowns = Cooldowns::default();
+        assert!(cooldowns.allow(&cooldown, "chan", "#a", "bob"));
+        cooldowns.record(&cooldown, "chan", "#a", "bob", "#a x");
+        // recorded once per message
+        assert!(cooldowns.allow(&cooldown, "chan", "#b", "bob"));
+        cooldowns.record_user("chan", "Bob");
+        assert!(!cooldowns.allow(&cooldown, "chan", "#b", "bob"));
+        assert!(cooldowns.allow(&cooldown, "chan", "#b", "ann"));
+        assert!(cooldowns.allow(&cooldown, "other", "#b", "bob"));
+        assert_eq!(cooldowns.submissions("chan", "BOB"), 1);
+
+        cooldowns.reset("chan");
+        assert!(cooldowns.allow(&cooldown, "chan", "#b", "bob"));
+        assert_eq!(cooldowns.submissions("chan", "bob"), 0);
+    }
+
+    #[test]
+    fn max_per_stream() {
+        let cooldown = Cooldown {
+            max_per_stream: 2,
+            ..Default::default()
+        };
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.record_user("chan", "bob");
+        assert!(cooldowns.allow(&cooldown, "chan", "#a", "bob"));
+        cooldowns.record_user("chan", "bob");
+        assert!(!cooldowns.allow(&cooldown, "chan", "#b", "bob"));
+        cooldowns.reset("other");
+        assert!(!cooldowns.allow(&cooldown, "chan", "#b", "bob"));
+    }
+
+    #[test]
+    fn tag_limits() {
+        let cooldown = Cooldown {
+            per_tag: 60,
+            ..Default::default()
+        };
+        let mut cooldowns = Cooldowns::default();
+        cooldowns.record(&cooldown, "chan", "#a", "bob", "#a x");
+        assert!(!cooldowns.allow(&cooldown, "chan", "#a", "ann"));
+        assert!(cooldowns.allow(&cooldown, "chan", "#b", "ann"));
+        assert_eq!(cooldowns.position("chan", "#a"), 1);
+        assert_eq!(cooldowns.position("chan", "#b"), 0);
+    }
+}
@@ -0,0 +1,262 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::{is_zero, Pattern};
+
+/// The known link policies of a filter.
+pub const LINK_POLICIES: [&str; 3] = ["allow", "deny", "allowlist"];
+
+fn is_allow_string(str: &str) -> bool {
+    str == "allow"
+}
+
+fn allow_string() -> String {
+    "allow".to_string()
+}
+
+/// Checks of the text before it is forwarded.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Filter {
+    // whole words or phrases, case insensitive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub blocked_words: Vec<String>,
+    // regular expressions, case insensitive
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub blocked_patterns: Vec<String>,
+    #[serde(skip)]
+    patterns: Vec<Pattern>,
+    // "allow", "deny" or "allowlist"
+    #[serde(skip_serializing_if = "is_allow_string")]
+    #[serde(default = "allow_string")]
+    pub links: String,
+    // domains (including subdomains) for "allowlist"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    // characters, 0 disables the limit
+    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default)]
+    pub min_length: usize,
+    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default)]
+    pub max_length: usize,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            blocked_words: vec![],
+            blocked_patterns: vec![],
+            patterns: vec![],
+            links: allow_string(),
+            allowed_domains: vec![],
+            min_length: 0,
+            max_length: 0,
+        }
+    }
+}
+
+// "https://example.com/x", "www.example.com/x" -> "example.com", "www.example.com"
+// without a scheme or "www." file names like "main.py" would be links
+fn link_regex() -> &'static Regex {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    LINK.get_or_init(|| {
+        Regex::new(
+            r"(?i)\b(?:https?://([a-z0-9-]+(?:\.[a-z0-9-]+)*)|(www\.(?:[a-z0-9-]+\.)+[a-z]{2,}))",
+        )
+        .unwrap()
+    })
+}
+
+// lowercase words separated by single spaces, with a space at both ends
+fn words(text: &str) -> String {
+    let words = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    format!(" {} ", words)
+}
+
+impl Filter {
+    pub fn is_disabled(&self) -> bool {
+        *self == Filter::default()
+    }
+
+    /// Compiles the blocked patterns, must be called before `check`.
+    pub fn compile(&mut self) -> anyhow::Result<()> {
+        if !LINK_POLICIES.contains(&self.links.as_str()) {
+            anyhow::bail!("Unknown link policy \"{}\"", self.links);
+        }
+        self.patterns = self
+            .blocked_patterns
+            .iter()
+            .map(|p| {
+                Pattern::new(&format!("(?i){}", p))
+                    .map_err(|e| anyhow::anyhow!("Invalid blocked pattern \"{}\": {}", p, e))
+            })
+            .collect::<anyhow::Result<Vec<Pattern>>>()?;
+        Ok(())
+    }
+
+    fn domain_allowed(&self, domain: &str) -> bool {
+        let domain = domain.to_lowercase();
+        self.allowed_domains.iter().any(|d| {
+            let d = d.trim().trim_start_matches('.').to_lowercase();
+            domain == d || domain.ends_with(&format!(".{}", d))
+        })
+    }
+
+    /// The reason if the text is rejected.
+    pub fn check(&self, text: &str) -> Result<(), String> {
+        let length = text.chars().count();
+        if self.min_length > 0 && length < self.min_length {
+            return Err(format!(
+                "too short ({} < {} characters)",
+                length, self.min_length
+            ));
+        }
+        if self.max_length > 0 && length > self.max_length {
+            return Err(format!(
+                "too long ({} > {} characters)",
+                length, self.max_length
+            ));
+        }
+        let text_words = words(text);
+        if let Some(w) = self
+            .blocked_words
+            .iter()
+            .find(|w| !words(w).trim().is_empty() && text_words.contains(&words(w)))
+        {
+            return Err(format!("blocked word \"{}\"", w));
+        }
+        if let Some(p) = self.patterns.iter().find(|p| p.0.is_match(text)) {
+            return Err(format!(
+                "blocked pattern \"{}\"",
+                p.0.as_str().trim_start_matches("(?i)")
+            ));
+        }
+        for link in link_regex().captures_iter(text) {
+            let domain = match link.get(1).or_else(|| link.get(2)) {
+                Some(domain) => domain.as_str(),
+                None => continue,
+            };
+            match self.links.as_str() {
+                "deny" => return Err(format!("link to {}", domain)),
+                "allowlist" if !self.domain_allowed(domain) => {
+                    return Err(format!("link to {} (not in the allowed domains)", domain))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn links(links: &str, allowed_domains: &[&str]) -> Filter {
+        let mut filter = Filter {
+            links: links.to_string(),
+            allowed_domains: allowed_domains.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        };
+        filter.compile().unwrap();
+        filter
+    }
+
+    #[test]
+    fn reasons() {
+        let mut filter = Filter {
+            blocked_words: vec!["bad word".to_string(), " ".to_string()],
+            blocked_patterns: vec!["^#q spam".to_string()],
+            min_length: 5,
+            max_length: 20,
+            ..Default::default()
+        };
+        filter.compile().unwrap();
+        assert_eq!(filter.check("#q a question"), Ok(()));
+        assert_eq!(
+            filter.check("#q"),
+            Err("too short (2 < 5 characters)".to_string())
+        );
+        assert_eq!(
+            filter.check("#q a very long question"),
+            Err("too long (23 > 20 characters)".to_string())
+        );
+        assert_eq!(
+            filter.check("#q a BAD, word"),
+            Err("blocked word \"bad word\"".to_string())
+        );
+        // whole words only
+        assert_eq!(filter.check("#q a bad wordle"), Ok(()));
+        assert_eq!(
+            filter.check("#Q SPAM"),
+            Err("blocked pattern \"^#q spam\"".to_string())
+        );
+    }
+
+    #[test]
+    fn invalid_filters() {
+        let mut filter = Filter {
+            links: "block".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.compile().unwrap_err().to_string(),
+            "Unknown link policy \"block\""
+        );
+        let mut filter = Filter {
+            blocked_patterns: vec!["(".to_string()],
+            ..Default::default()
+        };
+        let e = filter.compile().unwrap_err().to_string();
+        assert!(e.starts_with("Invalid blocked pattern \"(\": "), "{}", e);
+        assert!(!e.contains('\n'));
+        assert!(Filter::default().is_disabled());
+    }
+
+    #[test]
+    fn file_names_are_no_links() {
+        let filter = links("deny", &[]);
+        assert_eq!(filter.check("why does main.py crash?"), Ok(()));
+        assert_eq!(filter.check("node.js or deno?"), Ok(()));
+        assert_eq!(filter.check("where is config.json?"), Ok(()));
+        assert_eq!(filter.check("see example.com"), Ok(()));
+    }
+
+    #[test]
+    fn links_need_a_scheme_or_www() {
+        let filter = links("deny", &[]);
+        assert_eq!(
+            filter.check("see https://example.com/x?y=1"),
+            Err("link to example.com".to_string())
+        );
+        assert_eq!(
+            filter.check("HTTP://Example.com"),
+            Err("link to Example.com".to_string())
+        );
+        assert_eq!(
+            filter.check("see www.example.com/x"),
+            Err("link to www.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn allowed_domains() {
+        let filter = links("allowlist", &["youtube.com"]);
+        assert_eq!(filter.check("https://www.youtube.com/watch?v=x"), Ok(()));
+        assert_eq!(filter.check("https://youtube.com"), Ok(()));
+        assert_eq!(
+            filter.check("https://notyoutube.com"),
+            Err("link to notyoutube.com (not in the allowed domains)".to_string())
+        );
+        assert_eq!(filter.check("main.py and node.js"), Ok(()));
+    }
+}
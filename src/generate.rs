@@ -5,7 +5,7 @@ use dialoguer::{theme::ColorfulTheme, theme::Theme, Confirm, Input};
 
 use std::path::Path;
 
-use crate::{write_config_logged, BotConfig, Tag};
+use crate::{write_config_logged, BotConfig, ChannelConfig, Tag};
 
 pub fn generate() -> anyhow::Result<()> {
     let theme = ColorfulTheme::default();
@@ -41,18 +41,22 @@ pub fn generate() -> anyhow::Result<()> {
     //if prompt_boolean("Do you want to the webfrontend to manage tags?", false)? {}
 
     let config = BotConfig {
-        channel,
+        primary: ChannelConfig {
+            channel,
+            tags,
+            mods,
+            response_message_success,
+            response_message_failure,
+            ignore,
+            use_reply,
+            ..Default::default()
+        },
         username,
         oauth_token,
         key: "".to_string(),
-        mods,
         log_webhook,
-        response_message_success,
-        response_message_failure,
         whisper_response,
-        ignore,
-        tags,
-        use_reply,
+        ..Default::default()
     };
 
     info!("Generated config: {:#?}", config);
@@ -170,12 +174,7 @@ fn prompt_tags(theme: &dyn Theme) -> Result<Vec<Tag>, std::io::Error> {
             }
             let webhook = prompt_webhook(theme, "Webhook (empty to discard the tag)", true)?;
             if !webhook.is_empty() {
-                tags.push(Tag {
-                    tag,
-                    webhook,
-                    description: "".to_string(),
-                    channel_type: "channel".to_string(),
-                });
+                tags.push(crate::tag(tag, webhook));
             }
         }
     }
@@ -0,0 +1,351 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::BotConfig;
+
+pub const HELIX_URL: &str = "https://api.twitch.tv/helix";
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// profile pictures rarely change
+const AVATAR_TTL: Duration = Duration::from_secs(60 * 60);
+// the cache holds the chatters of the last hour, but not more than this
+const MAX_AVATARS: usize = 10_000;
+
+// login -> (time of the lookup, profile image)
+static AVATARS: Mutex<BTreeMap<String, (Instant, Option<String>)>> = Mutex::new(BTreeMap::new());
+
+// the user id of the bot (the owner of the token)
+static BOT_ID: Mutex<Option<String>> = Mutex::new(None);
+
+pub enum WhisperError {
+    // e.g. missing scope, the recipient doesn't accept whispers
+    NotPermitted(String),
+    Failed(String),
+}
+
+/// The twitch api, authenticated with the token of the bot.
+pub struct Helix {
+    client: reqwest::Client,
+    url: String,
+    client_id: String,
+    token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Data<T> {
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    profile_image_url: String,
+}
+
+// body of an error response
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    message: String,
+}
+
+// expired entries are dropped, and the oldest ones if there are still too many
+fn remember_avatar(
+    avatars: &mut BTreeMap<String, (Instant, Option<String>)>,
+    login: String,
+    url: Option<String>,
+) {
+    if avatars.len() >= MAX_AVATARS {
+        avatars.retain(|_, (time, _)| time.elapsed() < AVATAR_TTL);
+    }
+    while avatars.len() >= MAX_AVATARS {
+        let oldest = avatars
+            .iter()
+            .min_by_key(|(_, (time, _))| *time)
+            .map(|(login, _)| login.clone());
+        match oldest {
+            Some(login) => avatars.remove(&login),
+            None => break,
+        };
+    }
+    avatars.insert(login, (Instant::now(), url));
+}
+
+impl Helix {
+    /// Returns None if no client id is configured.
+    pub fn from_config(bc: &BotConfig) -> Option<Helix> {
+        if bc.client_id.is_empty() {
+            return None;
+        }
+        let client = match reqwest::Client::builder().timeout(TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                error!("Can't create the twitch api client: {}", e);
+                return None;
+            }
+        };
+        Some(Helix {
+            client,
+            url: if bc.helix_url.is_empty() {
+                HELIX_URL.to_string()
+            } else {
+                bc.helix_url.trim_end_matches('/').to_string()
+            },
+            client_id: bc.client_id.clone(),
+            token: bc.oauth_token.trim_start_matches("oauth:").to_string(),
+        })
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, String> {
+        let mut url =
+            reqwest::Url::parse(&format!("{}{}", self.url, path)).map_err(|e| e.to_string())?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        let resp = self
+            .client
+            .get(url)
+            .header("Client-Id", &self.client_id)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!(
+                "Code: {} Reason: {:?}",
+                resp.status(),
+                resp.status().canonical_reason()
+            ));
+        }
+        resp.json::<T>().await.map_err(|e| e.to_string())
+    }
+
+    async fn bot_id(&self) -> Result<String, String> {
+        if let Some(id) = BOT_ID.lock().unwrap().clone() {
+            return Ok(id);
+        }
+        // without a login the owner of the token is returned
+        let id = self
+            .get::<Data<User>>("/users", &[])
+            .await?
+            .data
+            .into_iter()
+            .next()
+            .ok_or("The token has no user")?
+            .id;
+        *BOT_ID.lock().unwrap() = Some(id.clone());
+        Ok(id)
+    }
+
+    /// Needs a user token of the bot with the scope `user:manage:whispers`.
+    pub async fn send_whisper(&self, to_user_id: &str, message: &str) -> Result<(), WhisperError> {
+        let from_user_id = self.bot_id().await.map_err(WhisperError::Failed)?;
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}/whispers", self.url),
+            &[
+                ("from_user_id", from_user_id.as_str()),
+                ("to_user_id", to_user_id),
+            ],
+        )
+        .map_err(|e| WhisperError::Failed(e.to_string()))?;
+        let resp = self
+            .client
+            .post(url)
+            .header("Client-Id", &self.client_id)
+            .bearer_auth(&self.token)
+            .json(&serde_json::json!({ "message": message }))
+            .send()
+            .await
+            .map_err(|e| WhisperError::Failed(e.to_string()))?;
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let error = match resp.json::<ApiError>().await {
+            Ok(e) if !e.message.is_empty() => format!("Code: {} {}", status, e.message),
+            _ => format!("Code: {} Reason: {:?}", status, status.canonical_reason()),
+        };
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            Err(WhisperError::NotPermitted(error))
+        } else {
+            Err(WhisperError::Failed(error))
+        }
+    }
+
+    /// The profile picture of the user, cached for an hour.
+    pub async fn avatar_url(&self, login: &str) -> Option<String> {
+        let login = login.to_lowercase();
+        if let Some((time, url)) = AVATARS.lock().unwrap().get(&login) {
+            if time.elapsed() < AVATAR_TTL {
+                return url.clone();
+            }
+        }
+        match self
+            .get::<Data<User>>("/users", &[("login", login.as_str())])
+            .await
+        {
+            Ok(users) => {
+                let url = users
+                    .data
+                    .into_iter()
+                    .map(|u| u.profile_image_url)
+                    .find(|u| !u.is_empty());
+                remember_avatar(&mut AVATARS.lock().unwrap(), login, url.clone());
+                url
+            }
+            Err(e) => {
+                // not cached, the next message tries again
+                warn!("Can't get the profile picture of {}: {}", login, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    // answers the requests with the responses in order, returns the helix
+    // and the request lines it received
+    fn stub(responses: Vec<(u16, &'static str)>) -> (Helix, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for ((status, body), stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                received.lock().unwrap().push(line.trim_end().to_string());
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                    if let Some(l) = header.to_lowercase().strip_prefix("content-length:") {
+                        length = l.trim().parse().unwrap();
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        let helix = Helix {
+            client: reqwest::Client::builder().timeout(TIMEOUT).build().unwrap(),
+            url,
+            client_id: "id".to_string(),
+            token: "token".to_string(),
+        };
+        (helix, requests)
+    }
+
+    const ANN: &str = r#"{"data": [{"id": "2", "profile_image_url": "https://img/ann"}]}"#;
+    const ANN_NEW: &str = r#"{"data": [{"id": "2", "profile_image_url": "https://img/ann2"}]}"#;
+
+    #[tokio::test]
+    async fn avatars_are_cached() {
+        let (helix, requests) = stub(vec![(500, ""), (200, ANN), (200, ANN_NEW)]);
+        // errors are not cached
+        assert_eq!(helix.avatar_url("Ann").await, None);
+        assert_eq!(
+            helix.avatar_url("Ann").await,
+            Some("https://img/ann".to_string())
+        );
+        assert_eq!(
+            helix.avatar_url("ann").await,
+            Some("https://img/ann".to_string())
+        );
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(requests.lock().unwrap()[1], "GET /users?login=ann HTTP/1.1");
+
+        // expired
+        AVATARS.lock().unwrap().get_mut("ann").unwrap().0 =
+            Instant::now().checked_sub(AVATAR_TTL).unwrap();
+        assert_eq!(
+            helix.avatar_url("ann").await,
+            Some("https://img/ann2".to_string())
+        );
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn avatar_cache_is_bounded() {
+        let mut avatars = BTreeMap::new();
+        for i in 1..MAX_AVATARS {
+            avatars.insert(i.to_string(), (Instant::now(), None));
+        }
+        let expired = Instant::now().checked_sub(AVATAR_TTL).unwrap();
+        avatars.insert("old".to_string(), (expired, None));
+        remember_avatar(&mut avatars, "ann".to_string(), None);
+        assert_eq!(avatars.len(), MAX_AVATARS);
+        assert!(!avatars.contains_key("old"));
+
+        let older = Instant::now().checked_sub(Duration::from_secs(1)).unwrap();
+        avatars.get_mut("1").unwrap().0 = older;
+        remember_avatar(&mut avatars, "bob".to_string(), None);
+        assert_eq!(avatars.len(), MAX_AVATARS);
+        assert!(!avatars.contains_key("1"));
+        assert!(avatars.contains_key("ann") && avatars.contains_key("bob"));
+    }
+
+    #[tokio::test]
+    async fn whispers() {
+        *BOT_ID.lock().unwrap() = Some("1".to_string());
+        let (helix, requests) = stub(vec![
+            (204, ""),
+            (401, r#"{"message": "Missing scope: user:manage:whispers"}"#),
+            (403, ""),
+            (500, ""),
+        ]);
+        assert!(helix.send_whisper("2", "hi").await.is_ok());
+        assert_eq!(
+            requests.lock().unwrap()[0],
+            "POST /whispers?from_user_id=1&to_user_id=2 HTTP/1.1"
+        );
+        match helix.send_whisper("2", "hi").await {
+            Err(WhisperError::NotPermitted(e)) => assert_eq!(
+                e,
+                "Code: 401 Unauthorized Missing scope: user:manage:whispers"
+            ),
+            _ => panic!("401 is not permitted"),
+        }
+        assert!(matches!(
+            helix.send_whisper("2", "hi").await,
+            Err(WhisperError::NotPermitted(_))
+        ));
+        assert!(matches!(
+            helix.send_whisper("2", "hi").await,
+            Err(WhisperError::Failed(_))
+        ));
+    }
+}
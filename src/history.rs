@@ -0,0 +1,585 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use rusqlite::types::Value;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::sink::Delivery;
+
+// entries per page of the cli
+const DEFAULT_LIMIT: usize = 50;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    message_id TEXT NOT NULL,
+    channel TEXT NOT NULL,
+    login TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    text TEXT NOT NULL,
+    timestamp INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_timestamp ON messages (timestamp);
+CREATE TABLE IF NOT EXISTS deliveries (
+    id INTEGER PRIMARY KEY,
+    message INTEGER NOT NULL REFERENCES messages (id),
+    tag TEXT NOT NULL,
+    status TEXT NOT NULL,
+    delivery TEXT
+);
+CREATE INDEX IF NOT EXISTS deliveries_message ON deliveries (message);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5 (
+    text, content = 'messages', content_rowid = 'id'
+);
+";
+
+/// What happened to a matched message for one tag.
+pub const DELIVERED: &str = "delivered";
+pub const FAILED: &str = "failed";
+pub const DUPLICATE: &str = "duplicate";
+pub const COOLDOWN: &str = "cooldown";
+pub const FILTERED: &str = "filtered";
+pub const DENIED: &str = "denied";
+pub const EMPTY: &str = "empty";
+
+/// A chat message that matched at least one tag.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub message_id: String,
+    pub channel: String,
+    pub login: String,
+    pub display_name: String,
+    pub text: String,
+    // unix time in seconds
+    pub timestamp: i64,
+}
+
+/// A matched message with the outcome for one of its tags.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Entry {
+    pub id: i64,
+    pub message_id: String,
+    pub channel: String,
+    pub login: String,
+    pub display_name: String,
+    pub text: String,
+    pub timestamp: i64,
+    pub tag: String,
+    pub status: String,
+    // what was posted, missing if the message was not forwarded
+    pub delivery: Option<Delivery>,
+}
+
+/// Filters of `query`, all of them are optional.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub channel: Option<String>,
+    pub tag: Option<String>,
+    pub user: Option<String>,
+    pub status: Option<String>,
+    // words that must all occur in the text
+    pub search: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// The archive is stored next to the config file, e.g. `config.history.sqlite`.
+pub fn history_file(config_file: &str) -> String {
+    Path::new(config_file)
+        .with_extension("history.sqlite")
+        .to_string_lossy()
+        .to_string()
+}
+
+// one connection per archive, shared by the message loop, the retries and the webfrontend
+static CONNECTIONS: Mutex<BTreeMap<String, Arc<Mutex<Connection>>>> = Mutex::new(BTreeMap::new());
+
+fn open(history_file: &str) -> rusqlite::Result<Arc<Mutex<Connection>>> {
+    let mut connections = CONNECTIONS.lock().unwrap();
+    if let Some(conn) = connections.get(history_file) {
+        return Ok(Arc::clone(conn));
+    }
+    let conn = Connection::open(history_file)?;
+    // e.g. the history cli in another process
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.execute_batch(SCHEMA)?;
+    let conn = Arc::new(Mutex::new(conn));
+    connections.insert(history_file.to_string(), Arc::clone(&conn));
+    Ok(conn)
+}
+
+/// Opens the archive of the bot before the first message, errors are only logged.
+pub fn start(config_file: &str) {
+    let history_file = history_file(config_file);
+    if let Err(e) = open(&history_file) {
+        error!("Can't open the history {}: {}", history_file, e);
+    }
+}
+
+fn insert(
+    history_file: &str,
+    message: &Message,
+    outcomes: &[(String, &str, Option<Delivery>)],
+) -> rusqlite::Result<Vec<i64>> {
+    let conn = open(history_file)?;
+    let mut conn = conn.lock().unwrap();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO messages (message_id, channel, login, display_name, text, timestamp)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            message.message_id,
+            message.channel,
+            message.login,
+            message.display_name,
+            message.text,
+            message.timestamp
+        ],
+    )?;
+    let id = tx.last_insert_rowid();
+    tx.execute(
+        "INSERT INTO messages_fts (rowid, text) VALUES (?1, ?2)",
+        params![id, message.text],
+    )?;
+    let mut ids = vec![];
+    for (tag, status, delivery) in outcomes {
+        let delivery = delivery
+            .as_ref()
+            .and_then(|d| serde_json::to_string(d).ok());
+        tx.execute(
+            "INSERT INTO deliveries (message, tag, status, delivery) VALUES (?1, ?2, ?3, ?4)",
+            params![id, tag, status, delivery],
+        )?;
+        ids.push(tx.last_insert_rowid());
+    }
+    tx.commit()?;
+    Ok(ids)
+}
+
+/// Stores the message with the outcome of each matched tag and returns the ids
+/// of the entries (in the order of the outcomes). Errors are only logged.
+pub async fn archive(
+    history_file: String,
+    message: Message,
+    outcomes: Vec<(String, &'static str, Option<Delivery>)>,
+) -> Vec<i64> {
+    if outcomes.is_empty() {
+        return vec![];
+    }
+    // sqlite blocks, e.g. while the webfrontend searches
+    let result = tokio::task::spawn_blocking(move || {
+        insert(&history_file, &message, &outcomes).map_err(|e| (message.message_id, e))
+    })
+    .await;
+    match result {
+        Ok(Ok(ids)) => ids,
+        Ok(Err((message_id, e))) => {
+            error!("Can't archive message {}: {}", message_id, e);
+            vec![]
+        }
+        Err(e) => {
+            error!("Can't archive message: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// E.g. when a queued message was delivered, errors are only logged.
+pub async fn set_status(history_file: String, id: i64, status: &'static str) {
+    let result = tokio::task::spawn_blocking(move || {
+        open(&history_file).and_then(|conn| {
+            conn.lock().unwrap().execute(
+                "UPDATE deliveries SET status = ?1 WHERE id = ?2",
+                params![status, id],
+            )
+        })
+    })
+    .await;
+    match result {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("Can't update the history entry {}: {}", id, e),
+        Err(e) => error!("Can't update the history entry {}: {}", id, e),
+    }
+}
+
+// every word quoted, the query syntax of fts5 is not exposed
+fn fts_query(search: &str) -> String {
+    search
+        .split_whitespace()
+        .map(|w| format!("\"{}\"", w.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    let delivery: Option<String> = row.get(9)?;
+    Ok(Entry {
+        id: row.get(0)?,
+        message_id: row.get(1)?,
+        channel: row.get(2)?,
+        login: row.get(3)?,
+        display_name: row.get(4)?,
+        text: row.get(5)?,
+        timestamp: row.get(6)?,
+        tag: row.get(7)?,
+        status: row.get(8)?,
+        delivery: delivery.and_then(|d| serde_json::from_str(&d).ok()),
+    })
+}
+
+const SELECT: &str = "SELECT d.id, m.message_id, m.channel, m.login, m.display_name, m.text,
+    m.timestamp, d.tag, d.status, d.delivery
+    FROM deliveries d JOIN messages m ON d.message = m.id";
+
+// the where clause of the filters of the query
+fn conditions(q: &Query) -> (String, Vec<Value>) {
+    let mut conditions = vec![];
+    let mut values: Vec<Value> = vec![];
+    if let Some(since) = q.since {
+        conditions.push("m.timestamp >= ?");
+        values.push(Value::Integer(since));
+    }
+    if let Some(until) = q.until {
+        conditions.push("m.timestamp < ?");
+        values.push(Value::Integer(until));
+    }
+    if let Some(channel) = &q.channel {
+        conditions.push("m.channel = ? COLLATE NOCASE");
+        values.push(Value::Text(channel.trim_start_matches('#').to_string()));
+    }
+    if let Some(tag) = &q.tag {
+        conditions.push("d.tag = ? COLLATE NOCASE");
+        values.push(Value::Text(tag.clone()));
+    }
+    if let Some(user) = &q.user {
+        conditions.push("m.login = ? COLLATE NOCASE");
+        values.push(Value::Text(user.trim_start_matches('@').to_string()));
+    }
+    if let Some(status) = &q.status {
+        conditions.push("d.status = ?");
+        values.push(Value::Text(status.clone()));
+    }
+    if let Some(search) = q.search.as_ref().filter(|s| !s.trim().is_empty()) {
+        conditions.push("m.id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?)");
+        values.push(Value::Text(fts_query(search)));
+    }
+    if conditions.is_empty() {
+        ("".to_string(), values)
+    } else {
+        (format!(" WHERE {}", conditions.join(" AND ")), values)
+    }
+}
+
+/// The newest entries first.
+pub fn query(history_file: &str, q: &Query) -> anyhow::Result<Vec<Entry>> {
+    if !Path::new(history_file).is_file() {
+        return Ok(vec![]);
+    }
+    let conn = open(history_file)?;
+    let conn = conn.lock().unwrap();
+    let (conditions, mut values) = conditions(q);
+    let sql = format!(
+        "{}{} ORDER BY m.timestamp DESC, d.id DESC LIMIT ? OFFSET ?",
+        SELECT, conditions
+    );
+    values.push(Value::Integer(q.limit as i64));
+    values.push(Value::Integer(q.offset as i64));
+    let mut stmt = conn.prepare(&sql)?;
+    let entries = stmt
+        .query_map(rusqlite::params_from_iter(values), entry)?
+        .collect::<rusqlite::Result<Vec<Entry>>>()?;
+    Ok(entries)
+}
+
+/// The number of entries matching the filters, `limit` and `offset` are ignored.
+#[cfg(feature = "webfrontend")]
+pub fn count(history_file: &str, q: &Query) -> anyhow::Result<usize> {
+    if !Path::new(history_file).is_file() {
+        return Ok(0);
+    }
+    let conn = open(history_file)?;
+    let conn = conn.lock().unwrap();
+    let (conditions, values) = conditions(q);
+    let sql = format!(
+        "SELECT COUNT(*) FROM deliveries d JOIN messages m ON d.message = m.id{}",
+        conditions
+    );
+    let count: i64 = conn.query_row(&sql, rusqlite::params_from_iter(values), |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+#[cfg(feature = "webfrontend")]
+pub fn get(history_file: &str, id: i64) -> anyhow::Result<Option<Entry>> {
+    if !Path::new(history_file).is_file() {
+        return Ok(None);
+    }
+    let conn = open(history_file)?;
+    let conn = conn.lock().unwrap();
+    let mut stmt = conn.prepare(&format!("{} WHERE d.id = ?1", SELECT))?;
+    let mut entries = stmt
+        .query_map(params![id], entry)?
+        .collect::<rusqlite::Result<Vec<Entry>>>()?;
+    Ok(entries.pop())
+}
+
+/// "2024-01-31" (local midnight, of the next day for `end`) or rfc 3339.
+pub fn parse_time(s: &str, end: bool) -> anyhow::Result<i64> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let date = if end {
+            date.succ_opt().unwrap_or(date)
+        } else {
+            date
+        };
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        return match Local.from_local_datetime(&midnight).earliest() {
+            Some(t) => Ok(t.timestamp()),
+            None => anyhow::bail!("Invalid date {}", s),
+        };
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.timestamp())
+        .map_err(|_| anyhow::anyhow!("Invalid date {} (expected YYYY-MM-DD)", s))
+}
+
+fn format_time(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(t) => t.format("%Y-%m-%d %H:%M").to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+const USAGE: &str = "usage: askbot history <config> [--since <date>] [--until <date>] \
+    [--channel <channel>] [--tag <tag>] [--user <login>] [--status <status>] \
+    [--search <words>] [--limit <n>] [--offset <n>]";
+
+fn parse_args(args: &[String]) -> anyhow::Result<Query> {
+    let mut q = Query {
+        limit: DEFAULT_LIMIT,
+        ..Default::default()
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = match args.next() {
+            Some(value) => value.clone(),
+            None => anyhow::bail!(USAGE),
+        };
+        match flag.as_str() {
+            "--since" => q.since = Some(parse_time(&value, false)?),
+            // the whole day is included
+            "--until" => q.until = Some(parse_time(&value, true)?),
+            "--channel" => q.channel = Some(value),
+            "--tag" => q.tag = Some(value),
+            "--user" => q.user = Some(value),
+            "--status" => q.status = Some(value),
+            "--search" => q.search = Some(value),
+            "--limit" => q.limit = value.parse()?,
+            "--offset" => q.offset = value.parse()?,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    Ok(q)
+}
+
+/// askbot history <config> [filters]
+pub fn cli(args: &[String]) -> anyhow::Result<()> {
+    let history_file = match args.first() {
+        Some(config_file) => history_file(config_file),
+        None => anyhow::bail!(USAGE),
+    };
+    if !Path::new(&history_file).is_file() {
+        anyhow::bail!("No history yet ({} is missing)", history_file);
+    }
+    let q = parse_args(&args[1..])?;
+    for e in query(&history_file, &q)? {
+        println!(
+            "{}\t{}\t#{}\t{}\t{}\t{}: {}",
+            e.id,
+            format_time(e.timestamp),
+            e.channel,
+            e.tag,
+            e.status,
+            e.login,
+            e.text
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn fts_queries() {
+        assert_eq!(fts_query("  hello   world "), "\"hello\" \"world\"");
+        // operators and quotes are searched as words
+        assert_eq!(
+            fts_query("a OR b NOT c"),
+            "\"a\" \"OR\" \"b\" \"NOT\" \"c\""
+        );
+        assert_eq!(fts_query("NEAR(a b*"), "\"NEAR(a\" \"b*\"");
+        assert_eq!(fts_query("say \"hi\""), "\"say\" \"\"\"hi\"\"\"");
+        assert_eq!(fts_query(""), "");
+    }
+
+    #[test]
+    fn where_clauses() {
+        let (sql, values) = conditions(&Query::default());
+        assert_eq!(sql, "");
+        assert!(values.is_empty());
+
+        let q = Query {
+            since: Some(10),
+            until: Some(20),
+            channel: Some("#Chan".to_string()),
+            tag: Some("#q".to_string()),
+            user: Some("@ann".to_string()),
+            status: Some(FAILED.to_string()),
+            search: Some("a\"b".to_string()),
+            ..Default::default()
+        };
+        let (sql, values) = conditions(&q);
+        assert_eq!(
+            sql,
+            " WHERE m.timestamp >= ? AND m.timestamp < ? AND m.channel = ? COLLATE NOCASE \
+            AND d.tag = ? COLLATE NOCASE AND m.login = ? COLLATE NOCASE AND d.status = ? \
+            AND m.id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?)"
+        );
+        assert_eq!(
+            values,
+            vec![
+                Value::Integer(10),
+                Value::Integer(20),
+                Value::Text("Chan".to_string()),
+                Value::Text("#q".to_string()),
+                Value::Text("ann".to_string()),
+                Value::Text("failed".to_string()),
+                Value::Text("\"a\"\"b\"".to_string()),
+            ]
+        );
+
+        // blank searches match everything
+        let q = Query {
+            search: Some("  ".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(conditions(&q).0, "");
+    }
+
+    #[test]
+    fn times() {
+        let start = parse_time("2024-01-15", false).unwrap();
+        let end = parse_time("2024-01-15", true).unwrap();
+        assert_eq!(end - start, 24 * 60 * 60);
+        assert_eq!(
+            parse_time("2024-01-01T00:00:00Z", false).unwrap(),
+            1704067200
+        );
+        assert_eq!(
+            parse_time("2024-01-01T01:00:00+01:00", true).unwrap(),
+            1704067200
+        );
+        assert!(parse_time("2024-13-01", false).is_err());
+        assert!(parse_time("yesterday", false).is_err());
+    }
+
+    #[test]
+    fn cli_args() {
+        let q = parse_args(&[]).unwrap();
+        assert_eq!(q.limit, DEFAULT_LIMIT);
+        assert_eq!(q.offset, 0);
+        assert!(q.search.is_none());
+
+        let q = parse_args(&args(&[
+            "--channel",
+            "#chan",
+            "--tag",
+            "#q",
+            "--user",
+            "ann",
+            "--status",
+            "delivered",
+            "--search",
+            "\"quoted\" words",
+            "--limit",
+            "5",
+            "--offset",
+            "10",
+            "--since",
+            "2024-01-01T00:00:00Z",
+        ]))
+        .unwrap();
+        assert_eq!(q.channel.as_deref(), Some("#chan"));
+        assert_eq!(q.tag.as_deref(), Some("#q"));
+        assert_eq!(q.user.as_deref(), Some("ann"));
+        assert_eq!(q.status.as_deref(), Some("delivered"));
+        assert_eq!(q.search.as_deref(), Some("\"quoted\" words"));
+        assert_eq!((q.limit, q.offset), (5, 10));
+        assert_eq!(q.since, Some(1704067200));
+
+        assert!(parse_args(&args(&["--tag"])).is_err());
+        assert!(parse_args(&args(&["--tags", "#q"])).is_err());
+        assert!(parse_args(&args(&["--limit", "many"])).is_err());
+        assert!(parse_args(&args(&["--until", "soon"])).is_err());
+    }
+
+    #[test]
+    fn search() {
+        let dir = std::env::temp_dir().join(format!("askbot-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let history_file = dir
+            .join("config.history.sqlite")
+            .to_string_lossy()
+            .to_string();
+        for (i, text) in ["#q say \"hi\" to me", "#q this OR that", "#q nothing"]
+            .iter()
+            .enumerate()
+        {
+            let message = Message {
+                message_id: i.to_string(),
+                channel: "chan".to_string(),
+                login: "ann".to_string(),
+                display_name: "Ann".to_string(),
+                text: text.to_string(),
+                timestamp: i as i64,
+            };
+            insert(
+                &history_file,
+                &message,
+                &[("#q".to_string(), DELIVERED, None)],
+            )
+            .unwrap();
+        }
+        let found = |search: &str| {
+            let q = Query {
+                search: Some(search.to_string()),
+                limit: DEFAULT_LIMIT,
+                ..Default::default()
+            };
+            query(&history_file, &q)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.message_id)
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(found("\"hi\""), vec!["0"]);
+        assert_eq!(found("OR"), vec!["1"]);
+        assert_eq!(found("this OR nothing"), Vec::<String>::new());
+        assert_eq!(found("q"), vec!["2", "1", "0"]);
+        assert_eq!(found("NEAR(say"), Vec::<String>::new());
+        CONNECTIONS.lock().unwrap().remove(&history_file);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
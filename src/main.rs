@@ -1,7 +1,12 @@
 #![cfg_attr(feature = "webfrontend", feature(proc_macro_hygiene, decl_macro))]
 
+use cooldown::{Cooldown, Cooldowns};
+use filter::Filter;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sink::{Delivery, Embed, SendError};
 use std::sync::{Arc, RwLock};
+use std::time::Instant;
 use twitch_irc::login::StaticLoginCredentials;
 use twitch_irc::ClientConfig;
 use twitch_irc::SecureTCPTransport;
@@ -17,26 +22,24 @@ extern crate rocket;
 #[cfg(feature = "webfrontend")]
 mod web;
 
+mod cooldown;
+mod filter;
 mod generate;
+mod helix;
+mod history;
+mod metrics;
+mod queue;
+mod ratelimit;
+mod reload;
+mod replay;
+mod sink;
+mod status;
+mod template;
 
-type IRCClient = twitch_irc::TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>;
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct Msg {
-    username: String,
-    avatar_url: Option<String>,
-    content: String,
-    thread_name: Option<String>,
-}
+// posted if nothing is left after removing the tag
+const RESPONSE_MESSAGE_EMPTY: &str = "Please include your question after the tag.";
 
-fn msg(username: String, content: String, thread_name: Option<String>) -> Msg {
-    Msg {
-        username,
-        avatar_url: None,
-        content,
-        thread_name,
-    }
-}
+type IRCClient = twitch_irc::TwitchIRCClient<SecureTCPTransport, StaticLoginCredentials>;
 
 fn is_channel_string(str: &str) -> bool {
     str == "channel"
@@ -46,6 +49,36 @@ fn channel_string() -> String {
     "channel".to_string()
 }
 
+fn is_literal_string(str: &str) -> bool {
+    str == "literal"
+}
+
+fn literal_string() -> String {
+    "literal".to_string()
+}
+
+// compiled glob or regex of a tag
+#[derive(Debug, Clone)]
+pub struct Pattern(Regex);
+
+impl Pattern {
+    /// The regex errors span multiple lines, they are joined into one.
+    fn new(re: &str) -> Result<Pattern, String> {
+        Regex::new(re).map(Pattern).map_err(|e| {
+            e.to_string()
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        })
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Tag {
     tag: String,
@@ -57,83 +90,473 @@ pub struct Tag {
     #[serde(default = "channel_string")]
     #[serde(alias = "type")]
     channel_type: String,
+    // "literal", "glob" or "regex"
+    #[serde(skip_serializing_if = "is_literal_string")]
+    #[serde(default = "literal_string")]
+    #[serde(alias = "match")]
+    match_mode: String,
+    #[serde(skip)]
+    pattern: Option<Pattern>,
+    // disabled tags are ignored
+    #[serde(skip_serializing_if = "bool_id")]
+    #[serde(default = "bool_true")]
+    enabled: bool,
+    // one of these badges is required, e.g. "subscriber", "vip", "moderator", "founder"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    required_badges: Vec<String>,
+    // minimum months subscribed (badge info of "subscriber" or "founder")
+    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default)]
+    min_sub_months: u32,
+    // "discord" or "slack"
+    #[serde(skip_serializing_if = "sink::is_discord_string")]
+    #[serde(default = "sink::discord_string")]
+    sink: String,
+    // remove the tag from the forwarded text (and the thread name)
+    #[serde(skip_serializing_if = "bool_not")]
+    #[serde(default)]
+    strip_tag: bool,
+    // name of the discord channel for {discord_channel}, default is the tag
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    discord_channel: String,
+    // templates of the forwarded message, see TAG_PLACEHOLDERS
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    content_template: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    username_template: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    thread_name_template: String,
+    // post the message as a discord embed with details of the chatter
+    #[serde(skip_serializing_if = "bool_not")]
+    #[serde(default)]
+    embed: bool,
+    // overrides the cooldown of the channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    cooldown: Option<Cooldown>,
+    // checked in addition to the filter of the bot
+    #[serde(skip_serializing_if = "Filter::is_disabled")]
+    #[serde(default)]
+    filter: Filter,
+}
+
+fn tag(tag: String, webhook: String) -> Tag {
+    Tag {
+        tag,
+        webhook,
+        description: "".to_string(),
+        channel_type: channel_string(),
+        match_mode: literal_string(),
+        pattern: None,
+        enabled: true,
+        required_badges: vec![],
+        min_sub_months: 0,
+        sink: sink::discord_string(),
+        strip_tag: false,
+        discord_channel: "".to_string(),
+        content_template: "".to_string(),
+        username_template: "".to_string(),
+        thread_name_template: "".to_string(),
+        embed: false,
+        cooldown: None,
+        filter: Filter::default(),
+    }
+}
+
+/// The placeholders of the content, username and thread name templates of a tag.
+const TAG_PLACEHOLDERS: [&str; 7] = [
+    "text",
+    "login",
+    "display_name",
+    "channel",
+    "tag",
+    "timestamp",
+    "message_id",
+];
+
+// a glob matches a single word, "*" and "?" don't match whitespace
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = "(?i)(^|\\s)".to_string();
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str("\\S*"),
+            '?' => re.push_str("\\S"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re + "(\\s|$)"
+}
+
+impl Tag {
+    /// Compiles glob and regex tags, must be called before `matches`.
+    fn compile(&mut self) -> anyhow::Result<()> {
+        if !sink::SINKS.contains(&self.sink.as_str()) {
+            anyhow::bail!("Unknown sink \"{}\" of tag {}", self.sink, self.tag);
+        }
+        if self.sink == "slack" && (self.channel_type == "forum" || self.embed) {
+            anyhow::bail!("Slack doesn't support forums or embeds (tag {})", self.tag);
+        }
+        for t in [
+            &self.content_template,
+            &self.username_template,
+            &self.thread_name_template,
+        ] {
+            template::check(t, &TAG_PLACEHOLDERS)
+                .map_err(|e| anyhow::anyhow!("{} (tag {})", e, self.tag))?;
+        }
+        self.filter
+            .compile()
+            .map_err(|e| anyhow::anyhow!("{} (filter of tag {})", e, self.tag))?;
+        let re = match self.match_mode.as_str() {
+            "literal" => None,
+            "glob" => Some(glob_to_regex(&self.tag)),
+            "regex" => Some(format!("(?i){}", self.tag)),
+            m => anyhow::bail!("Unknown match mode \"{}\" of tag {}", m, self.tag),
+        };
+        self.pattern = match re {
+            Some(re) => Some(Pattern::new(&re).map_err(|e| {
+                anyhow::anyhow!(
+                    "Invalid {} pattern \"{}\": {}",
+                    self.match_mode,
+                    self.tag,
+                    e
+                )
+            })?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    fn matches(&self, text_lower: &str) -> bool {
+        match &self.pattern {
+            Some(Pattern(re)) => re.is_match(text_lower),
+            None => {
+                text_lower.contains(&(self.tag.to_lowercase() + " "))
+                    || text_lower.ends_with(&self.tag.to_lowercase())
+            }
+        }
+    }
+
+    /// Whether the sender has the required badges, the broadcaster is always permitted.
+    fn permits(&self, privmsg: &twitch_irc::message::PrivmsgMessage) -> bool {
+        if privmsg.badges.iter().any(|b| b.name == "broadcaster") {
+            return true;
+        }
+        let has_badge = self.required_badges.is_empty()
+            || privmsg.badges.iter().any(|b| {
+                self.required_badges
+                    .iter()
+                    .any(|r| r.eq_ignore_ascii_case(&b.name))
+            });
+        let months = privmsg
+            .badge_info
+            .iter()
+            .find(|b| b.name == "subscriber" || b.name == "founder")
+            .and_then(|b| b.version.parse::<u32>().ok())
+            .unwrap_or(0);
+        has_badge && months >= self.min_sub_months
+    }
+
+    fn discord_channel(&self) -> String {
+        if self.discord_channel.is_empty() {
+            self.tag.trim_start_matches('#').to_string()
+        } else {
+            self.discord_channel.clone()
+        }
+    }
+
+    /// The text without the (matched) tag.
+    fn strip(&self, text: &str) -> String {
+        let text = match &self.pattern {
+            Some(Pattern(re)) => re.replace_all(text, " ").to_string(),
+            None => text
+                .split_whitespace()
+                .filter(|w| w.to_lowercase() != self.tag.to_lowercase())
+                .collect::<Vec<&str>>()
+                .join(" "),
+        };
+        text.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    fn delivery(&self, privmsg: &twitch_irc::message::PrivmsgMessage, text: String) -> Delivery {
+        let timestamp = privmsg.server_timestamp.to_rfc3339();
+        let values = [
+            ("text", text.as_str()),
+            ("login", privmsg.sender.login.as_str()),
+            ("display_name", privmsg.sender.name.as_str()),
+            ("channel", privmsg.channel_login.as_str()),
+            ("tag", self.tag.as_str()),
+            ("timestamp", timestamp.as_str()),
+            ("message_id", privmsg.message_id.as_str()),
+        ];
+        let render = |t: &str, default: &str| {
+            if t.is_empty() {
+                default.to_string()
+            } else {
+                template::render(t, &values)
+            }
+        };
+        let forum = self.channel_type == "forum";
+        let thread_name = if forum && !self.thread_name_template.is_empty() {
+            Some(strip_title(&template::render(
+                &self.thread_name_template,
+                &values,
+            )))
+        } else {
+            None
+        };
+        let embed = if self.embed {
+            Some(Embed {
+                display_name: privmsg.sender.name.clone(),
+                color: privmsg
+                    .name_color
+                    .as_ref()
+                    .map(|c| (c.r as u32) << 16 | (c.g as u32) << 8 | c.b as u32),
+                badges: privmsg
+                    .badges
+                    .iter()
+                    .map(|b| format!("{}/{}", b.name, b.version))
+                    .collect(),
+                timestamp: timestamp.clone(),
+                channel: privmsg.channel_login.clone(),
+                tag: self.tag.clone(),
+            })
+        } else {
+            None
+        };
+        Delivery {
+            sink: self.sink.clone(),
+            webhook: self.webhook.clone(),
+            sender: render(&self.username_template, &privmsg.sender.login),
+            text: render(&self.content_template, &text),
+            forum,
+            thread_name,
+            embed,
+            avatar_url: None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct BotConfig {
+pub struct ChannelConfig {
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
     channel: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    tags: Vec<Tag>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    mods: Vec<String>,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    username: String,
+    response_message_success: String,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    oauth_token: String,
+    response_message_failure: String,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
-    tags: Vec<Tag>,
+    ignore: Vec<String>,
+    #[serde(skip_serializing_if = "bool_id")]
+    #[serde(default = "bool_true")]
+    use_reply: bool,
+    #[serde(skip_serializing_if = "Cooldown::is_disabled")]
+    #[serde(default)]
+    cooldown: Cooldown,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    key: String,
+    response_message_cooldown: String,
+    // empty means RESPONSE_MESSAGE_EMPTY
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    response_message_empty: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    response_message_denied: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BotConfig {
+    // the first channel is configured on the top level (backwards compatible)
+    #[serde(flatten)]
+    primary: ChannelConfig,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
-    mods: Vec<String>,
+    channels: Vec<ChannelConfig>,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    log_webhook: String,
+    username: String,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    response_message_success: String,
+    oauth_token: String,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    response_message_failure: String,
+    key: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    log_webhook: String,
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
     whisper_response: String,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    // the twitch api is only used if the client id (of the oauth token) is set
+    #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
-    ignore: Vec<String>,
-    #[serde(skip_serializing_if = "bool_id")]
-    #[serde(default = "bool_true")]
-    use_reply: bool,
+    client_id: String,
+    // empty means helix::HELIX_URL
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    helix_url: String,
+    // prometheus metrics on http://<host>:<port>/metrics, 0 disables them
+    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default)]
+    metrics_port: u16,
+    // the address the metrics are served on, empty means metrics::METRICS_ADDRESS
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    metrics_address: String,
+    // applies to the tags of all channels
+    #[serde(skip_serializing_if = "Filter::is_disabled")]
+    #[serde(default)]
+    filter: Filter,
+}
+
+impl BotConfig {
+    fn channels(&self) -> impl Iterator<Item = &ChannelConfig> {
+        std::iter::once(&self.primary).chain(self.channels.iter())
+    }
+
+    fn channel(&self, login: &str) -> Option<&ChannelConfig> {
+        self.channels()
+            .find(|c| c.channel.to_lowercase() == login.to_lowercase())
+    }
+
+    fn channels_mut(&mut self) -> impl Iterator<Item = &mut ChannelConfig> {
+        std::iter::once(&mut self.primary).chain(self.channels.iter_mut())
+    }
+
+    fn channel_mut(&mut self, login: &str) -> Option<&mut ChannelConfig> {
+        self.channels_mut()
+            .find(|c| c.channel.to_lowercase() == login.to_lowercase())
+    }
+
+    /// Compiles the tags and the filters.
+    fn compile(&mut self) -> anyhow::Result<()> {
+        for c in self.channels_mut() {
+            for t in c.tags.iter_mut() {
+                t.compile()?;
+            }
+        }
+        self.filter.compile()?;
+        Ok(())
+    }
 }
 
 fn bool_id(a: &bool) -> bool {
     *a
 }
 
+fn is_zero<T: Default + PartialEq>(a: &T) -> bool {
+    *a == T::default()
+}
+
+fn bool_not(a: &bool) -> bool {
+    !*a
+}
+
 fn bool_true() -> bool {
     true
 }
 
-async fn say_in_response<T>(channel: String, client: &IRCClient, msg: T, reply_to: Option<String>)
-where
-    T: Into<String>,
-{
-    if let Some(reply_message) = reply_to {
-        if let Err(e) = client.say_in_reply_to(&(channel,reply_message), msg.into()).await {
+/// Everything the bot sends: chat messages, whispers and webhook posts.
+/// Implemented by the twitch client and by the recorder of the replay mode.
+trait Output {
+    async fn chat(&self, channel: String, msg: String, reply_to: Option<String>);
+    // whispers need the twitch api, the channel (of a mod command) is used if they are not permitted
+    async fn whisper(
+        &self,
+        helix: Option<&helix::Helix>,
+        channel: Option<String>,
+        user: String,
+        user_id: String,
+        msg: String,
+    );
+    async fn post(&self, delivery: &Delivery) -> Result<(), SendError>;
+    // the name of a new forum thread
+    async fn thread_name(&self, text: &str) -> String;
+}
+
+impl Output for IRCClient {
+    async fn chat(&self, channel: String, msg: String, reply_to: Option<String>) {
+        metrics::chat_response();
+        if let Some(reply_message) = reply_to {
+            if let Err(e) = self.say_in_reply_to(&(channel, reply_message), msg).await {
+                error!("Error: {}", e);
+            }
+        } else if let Err(e) = self.say(channel, msg).await {
             error!("Error: {}", e);
         }
-    } else {
-        if let Err(e) = client.say(channel,msg.into()).await {
-            error!("Error: {}", e);
+    }
+
+    async fn whisper(
+        &self,
+        helix: Option<&helix::Helix>,
+        channel: Option<String>,
+        user: String,
+        user_id: String,
+        msg: String,
+    ) {
+        let helix = match helix {
+            Some(helix) => helix,
+            None => {
+                warn!("No client_id for whispers, not answered {}: {}", user, msg);
+                return;
+            }
+        };
+        match (helix.send_whisper(&user_id, &msg).await, channel) {
+            (Ok(()), _) => (),
+            (Err(helix::WhisperError::NotPermitted(e)), Some(channel)) => {
+                warn!(
+                    "Whisper to {} not permitted, reply in #{}: {}",
+                    user, channel, e
+                );
+                self.chat(channel, format!("@{} {}", user, msg), None).await;
+            }
+            (Err(helix::WhisperError::NotPermitted(e)), None) => {
+                warn!("Whisper to {} not permitted: {}", user, e)
+            }
+            (Err(helix::WhisperError::Failed(e)), _) => {
+                error!("Can't whisper to {}: {}", user, e)
+            }
         }
     }
+
+    async fn post(&self, delivery: &Delivery) -> Result<(), SendError> {
+        sink::send_message(delivery).await
+    }
+
+    async fn thread_name(&self, text: &str) -> String {
+        thread_name(text).await
+    }
 }
 
-async fn privmsg<T>(channel: String, client: &IRCClient, msg: T)
+async fn say_in_response<T, C>(channel: String, client: &C, msg: T, reply_to: Option<String>)
 where
     T: Into<String>,
+    C: Output,
 {
-    match client.privmsg(channel, msg.into()).await {
-        Ok(_) => (),
-        Err(e) => error!("{}", e),
-    };
+    client.chat(channel, msg.into(), reply_to).await;
 }
 
-pub fn read_config(config_file: &str) -> std::result::Result<BotConfig, serde_any::Error> {
-    serde_any::from_file(config_file)
+pub fn read_config(config_file: &str) -> anyhow::Result<BotConfig> {
+    let mut bc: BotConfig = serde_any::from_file(config_file)
+        .map_err(|e| anyhow::anyhow!("Can't read config file {}: {}", config_file, e))?;
+    bc.compile()?;
+    Ok(bc)
 }
 
 pub fn write_config_logged(config_file: &str, bc: &BotConfig) {
@@ -150,9 +573,13 @@ fn is_mod(badges: &[twitch_irc::message::Badge]) -> bool {
 }
 
 fn strip_title(title: &str) -> String {
-    let end = title.len().min(80);
-    if end < 80 {
-        title[..end].to_string()
+    // 80 characters, not bytes, the title is user text
+    let end = title
+        .char_indices()
+        .nth(80)
+        .map_or(title.len(), |(end, _)| end);
+    if end == title.len() {
+        title.to_string()
     } else {
         if let Some(pos) = title[..end].rfind(" ") {
             title[..pos].to_string() + "..."
@@ -186,40 +613,48 @@ async fn thread_title(text: &str) -> Option<String> {
     Some(strip_title(text))
 }
 
-async fn send_message(webhook: &str, sender: String, text: String, forum: bool) -> bool {
-    let client = reqwest::Client::new();
-    let thread_name = if forum {
-        if let Some(title) = thread_title(&text).await {
-            Some(title)
-        } else {
-            Some(strip_title(&text))
-        }
+/// The name of a new forum thread for the text.
+async fn thread_name(text: &str) -> String {
+    match thread_title(text).await {
+        Some(title) => title,
+        None => strip_title(text),
+    }
+}
 
-    } else {
-        None
-    };
-    match client
-        .post(webhook)
-        .json(&msg(sender, text, thread_name))
-        .send()
-        .await
-    {
-        Ok(resp) => {
-            if resp.status().is_success() {
-                return true;
-            } else {
-                error!(
-                    "Error: Code: {} Reason: {:?}",
-                    resp.status(),
-                    resp.status().canonical_reason()
-                );
-            }
-        }
-        Err(e) => {
-            error!("Error: {}", e);
-        }
-    };
-    false
+// the tags are the forwarded (or rejected) tags of the message
+fn render_response(
+    template: &str,
+    user: &str,
+    tags: &[Tag],
+    cooldowns: &Cooldowns,
+    channel: &str,
+    login: &str,
+) -> String {
+    let tag_names = tags
+        .iter()
+        .map(|t| t.tag.clone())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let count = cooldowns.submissions(channel, login).to_string();
+    let position = tags
+        .first()
+        .map(|t| cooldowns.position(channel, &t.tag))
+        .unwrap_or(0)
+        .to_string();
+    let discord_channel = tags
+        .first()
+        .map(|t| t.discord_channel())
+        .unwrap_or_default();
+    template::render_text(
+        template,
+        &[
+            ("user", user),
+            ("tags", &tag_names),
+            ("count", &count),
+            ("position", &position),
+            ("discord_channel", &discord_channel),
+        ],
+    )
 }
 
 fn mention(m: &str) -> Option<String> {
@@ -234,242 +669,748 @@ fn mention(m: &str) -> Option<String> {
     }
 }
 
-async fn send_messages(
+async fn send_messages<C: Output>(
     irc_bc: &Arc<RwLock<BotConfig>>,
-    message_text: String,
-    sender: &twitch_irc::message::TwitchUserBasics,
-    client: &IRCClient,
-    message_id: String,
+    config_file: &str,
+    privmsg: &twitch_irc::message::PrivmsgMessage,
+    client: &C,
+    cooldowns: &mut Cooldowns,
 ) {
+    let channel_login = privmsg.channel_login.clone();
+    let message_text = &privmsg.message_text;
+    let sender = &privmsg.sender;
+    let message_id = privmsg.message_id.clone();
     let mut sended = false;
     let mut success = true;
+    let mut limited = false;
+    let mut empty = false;
+    let mut denied = false;
+    let mut forwarded = vec![];
+    let mut rejected = vec![];
+    // tag, status and delivery for the archive
+    let mut outcomes = vec![];
+    // index of the outcome and error of failed posts
+    let mut errors = vec![];
     {
         // TODO: copy tags to avoid RwLock/await overlapping (send_message)
-        let (tags, channel) = {
+        let (tags, cooldown, filter, helix) = {
             let bc = irc_bc.read().unwrap();
-            (bc.tags.clone(), bc.channel.clone())
+            match bc.channel(&channel_login) {
+                Some(c) => (
+                    c.tags.clone(),
+                    c.cooldown,
+                    bc.filter.clone(),
+                    helix::Helix::from_config(&bc),
+                ),
+                None => return,
+            }
         };
         for t in tags {
-            let is_forum = t.channel_type == "forum";
-            if t.tag.is_empty() {
+            if t.tag.is_empty() || !t.enabled {
                 continue;
             }
             let text_lower = message_text.to_lowercase();
             let command = t.tag.replacen("#", "!", 1);
-            if text_lower.contains(&(t.tag.to_lowercase() + " "))
-                || text_lower.ends_with(&t.tag.to_lowercase())
-                || (text_lower.starts_with(&command) && !t.description.is_empty())
+            let is_literal = t.pattern.is_none();
+            if t.matches(&text_lower)
+                || (is_literal && text_lower.starts_with(&command) && !t.description.is_empty())
             {
-                if text_lower == command {
+                let description = || {
+                    render_response(
+                        &t.description,
+                        &sender.name,
+                        std::slice::from_ref(&t),
+                        cooldowns,
+                        &channel_login,
+                        &sender.login,
+                    )
+                };
+                if is_literal && text_lower == command {
                     say_in_response(
-                        channel.clone(),
+                        channel_login.clone(),
                         client,
-                        t.description.clone(),
+                        description(),
                         Some(message_id.clone()),
                     )
                     .await;
                     continue;
-                } else if let Some(user) = mention(&text_lower) {
+                } else if let Some(user) = mention(&text_lower).filter(|_| is_literal) {
                     say_in_response(
-                        channel.clone(),
+                        channel_login.clone(),
                         client,
-                        format!("{} {}", user, t.description).to_string(),
+                        format!("{} {}", user, description()).to_string(),
                         None,
                     )
                     .await;
                     continue;
                 }
-                success = success
-                    && send_message(
-                        &t.webhook,
-                        sender.login.clone(),
-                        message_text.clone(),
-                        is_forum,
+                metrics::tag_match(&channel_login, &t.tag);
+                if !t.permits(privmsg) {
+                    info!(
+                        "Denied: {} ({}) by {}",
+                        &t.tag, &channel_login, &sender.login
+                    );
+                    denied = true;
+                    outcomes.push((t.tag.clone(), history::DENIED, None));
+                    rejected.push(t);
+                    continue;
+                }
+                let text = if t.strip_tag {
+                    t.strip(message_text)
+                } else {
+                    message_text.clone()
+                };
+                if text.is_empty() {
+                    info!(
+                        "Empty: {} ({}) by {}",
+                        &t.tag, &channel_login, &sender.login
+                    );
+                    empty = true;
+                    outcomes.push((t.tag.clone(), history::EMPTY, None));
+                    rejected.push(t);
+                    continue;
+                }
+                if let Err(reason) = filter.check(&text).and_then(|_| t.filter.check(&text)) {
+                    info!(
+                        "Filtered: {} ({}) by {}: {}",
+                        &t.tag, &channel_login, &sender.login, reason
+                    );
+                    log_on_discord(
+                        irc_bc,
+                        client,
+                        &format!(
+                            "Not forwarded to {} ({}): {}\n{}: {}",
+                            &t.tag, &channel_login, reason, &sender.login, message_text
+                        ),
                     )
                     .await;
+                    outcomes.push((t.tag.clone(), history::FILTERED, None));
+                    continue;
+                }
+                let cooldown = t.cooldown.map_or(cooldown, |c| c.merged(&cooldown));
+                if cooldowns.is_duplicate(&cooldown, &channel_login, &t.tag, &sender.login, &text) {
+                    info!(
+                        "Duplicate: {} ({}) by {}",
+                        &t.tag, &channel_login, &sender.login
+                    );
+                    outcomes.push((t.tag.clone(), history::DUPLICATE, None));
+                    continue;
+                }
+                if !cooldowns.allow(&cooldown, &channel_login, &t.tag, &sender.login) {
+                    info!(
+                        "Cooldown: {} ({}) by {}",
+                        &t.tag, &channel_login, &sender.login
+                    );
+                    limited = true;
+                    outcomes.push((t.tag.clone(), history::COOLDOWN, None));
+                    rejected.push(t);
+                    continue;
+                }
+                let mut delivery = t.delivery(privmsg, text.clone());
+                if delivery.forum && delivery.thread_name.is_none() {
+                    // from the chat text, not from the content template
+                    delivery.thread_name = Some(client.thread_name(&text).await);
+                }
+                if let Some(helix) = &helix {
+                    delivery.avatar_url = helix.avatar_url(&sender.login).await;
+                }
+                let start = Instant::now();
+                let result = client.post(&delivery).await;
+                metrics::webhook_post(&channel_login, &t.tag, result.is_ok(), start.elapsed());
+                if let Err(e) = result {
+                    status::webhook_error(&t.tag, &e.message);
+                    errors.push((outcomes.len(), e));
+                    outcomes.push((t.tag.clone(), history::FAILED, Some(delivery)));
+                    success = false;
+                } else {
+                    // failed posts don't count for the cooldowns
+                    cooldowns.record(&cooldown, &channel_login, &t.tag, &sender.login, &text);
+                    outcomes.push((t.tag.clone(), history::DELIVERED, Some(delivery)));
+                }
                 sended = true;
+                forwarded.push(t);
             }
         }
     }
+    // once per message, the other tags of the message were checked before
+    if outcomes
+        .iter()
+        .any(|(_, status, _)| *status == history::DELIVERED)
+    {
+        cooldowns.record_user(&channel_login, &sender.login);
+    }
+    let history_ids = history::archive(
+        history::history_file(config_file),
+        history::Message {
+            message_id: message_id.clone(),
+            channel: channel_login.clone(),
+            login: sender.login.clone(),
+            display_name: sender.name.clone(),
+            text: message_text.clone(),
+            timestamp: privmsg.server_timestamp.timestamp(),
+        },
+        outcomes.clone(),
+    )
+    .await;
+    // queued after archiving, a successful retry updates the archive
+    for (i, e) in errors {
+        if e.permanent {
+            error!("Not queued for {} (permanent error): {}", outcomes[i].0, e);
+            continue;
+        }
+        if let (tag, _, Some(delivery)) = &outcomes[i] {
+            queue::push(
+                &queue::queue_file(config_file),
+                channel_login.clone(),
+                tag.clone(),
+                delivery.clone(),
+                e.message,
+                history_ids.get(i).copied(),
+            )
+            .await;
+        }
+    }
 
-    let mut message: (String, String) = ("".to_string(), "".to_string());
+    let mut message = "".to_string();
+    let mut reply = true;
     if sended {
         let bc = irc_bc.read().unwrap();
-        if success {
-            message = (bc.response_message_success.clone(), bc.channel.clone());
-        } else {
-            message = (bc.response_message_failure.clone(), bc.channel.clone());
+        if let Some(c) = bc.channel(&channel_login) {
+            if success {
+                message = c.response_message_success.clone();
+            } else {
+                message = c.response_message_failure.clone();
+            }
+            reply = c.use_reply;
+        }
+    } else if limited {
+        let bc = irc_bc.read().unwrap();
+        if let Some(c) = bc.channel(&channel_login) {
+            message = c.response_message_cooldown.clone();
+            reply = c.use_reply;
+        }
+    } else if empty {
+        let bc = irc_bc.read().unwrap();
+        if let Some(c) = bc.channel(&channel_login) {
+            message = if c.response_message_empty.is_empty() {
+                RESPONSE_MESSAGE_EMPTY.to_string()
+            } else {
+                c.response_message_empty.clone()
+            };
+            reply = c.use_reply;
+        }
+    } else if denied {
+        let bc = irc_bc.read().unwrap();
+        if let Some(c) = bc.channel(&channel_login) {
+            message = c.response_message_denied.clone();
+            reply = c.use_reply;
         }
     }
-    if !message.0.is_empty() {
-        let reply = {
-            let bc = irc_bc.read().unwrap();
-            bc.use_reply
-        };
+    if !message.is_empty() {
+        let message = render_response(
+            &message,
+            &sender.name,
+            if sended { &forwarded } else { &rejected },
+            cooldowns,
+            &channel_login,
+            &sender.login,
+        );
         let msg = if reply {
-            message.0
+            message
         } else {
-            format!("@{}: {}", &sender.login, message.0)
+            format!("@{}: {}", &sender.login, message)
         };
         let reply_id = if reply { Some(message_id) } else { None };
-        say_in_response(message.1, client, msg, reply_id).await;
+        say_in_response(channel_login, client, msg, reply_id).await;
     }
 }
 
-enum Whisper {
-    Add(String, String),
+#[derive(Debug, PartialEq)]
+enum Command {
+    Add(String, String, String),
     Remove(String),
     List,
+    Describe(String, String),
+    Type(String, String),
+    Rename(String, String),
+    Webhook(String, String),
+    AddMod(String),
+    RemoveMod(String),
+    ListMods,
+    AddIgnore(String),
+    RemoveIgnore(String),
+    ListIgnored,
+    Enable(String),
+    Disable(String),
     Nothing,
 }
 
-fn parse_whisper(bc: &BotConfig, login: &str, message_text: String) -> Whisper {
-    if bc.mods.iter().any(|m| *m == *login) || *login == bc.channel {
-        match message_text.split_whitespace().collect::<Vec<&str>>()[..] {
-            ["#list"] => Whisper::List,
-            ["#add", tag, webhook] => Whisper::Add(tag.to_string(), webhook.to_string()),
-            ["#remove", tag] => Whisper::Remove(tag.to_string()),
-            _ => Whisper::Nothing,
+// split at whitespace, "quoted text" is one argument
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    let mut quoted = false;
+    let mut in_arg = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    args
+}
+
+fn parse_whisper(message_text: &str) -> Command {
+    let args = split_args(message_text);
+    match args.iter().map(|s| s.as_str()).collect::<Vec<&str>>()[..] {
+        ["#list"] => Command::List,
+        ["#add", tag, webhook] => {
+            Command::Add(tag.to_string(), webhook.to_string(), literal_string())
         }
+        ["#add", tag, webhook, mode] => {
+            Command::Add(tag.to_string(), webhook.to_string(), mode.to_lowercase())
+        }
+        ["#remove", tag] => Command::Remove(tag.to_string()),
+        ["#describe", tag, description] => {
+            Command::Describe(tag.to_string(), description.to_string())
+        }
+        ["#type", tag, channel_type] => Command::Type(tag.to_string(), channel_type.to_lowercase()),
+        ["#rename", tag, new_tag] => Command::Rename(tag.to_string(), new_tag.to_string()),
+        ["#webhook", tag, webhook] => Command::Webhook(tag.to_string(), webhook.to_string()),
+        ["#mod", "add", name] => Command::AddMod(name.to_lowercase()),
+        ["#mod", "remove", name] => Command::RemoveMod(name.to_lowercase()),
+        ["#mods"] => Command::ListMods,
+        ["#ignore", "add", name] => Command::AddIgnore(name.to_lowercase()),
+        ["#ignore", "remove", name] => Command::RemoveIgnore(name.to_lowercase()),
+        ["#ignored"] => Command::ListIgnored,
+        ["#enable", tag] => Command::Enable(tag.to_string()),
+        ["#disable", tag] => Command::Disable(tag.to_string()),
+        _ => Command::Nothing,
+    }
+}
+
+const CHAT_COMMAND_USAGE: &str =
+    "Usage: !askbot add <tag> <webhook> [glob|regex] | remove <tag> | list | enable <tag> | disable <tag>";
+
+// "!askbot ..." of moderators in the chat
+fn parse_chat_command(message_text: &str) -> Command {
+    let args = split_args(message_text);
+    match args
+        .iter()
+        .skip(1)
+        .map(|s| s.as_str())
+        .collect::<Vec<&str>>()[..]
+    {
+        ["add", tag, webhook] => {
+            Command::Add(tag.to_string(), webhook.to_string(), literal_string())
+        }
+        ["add", tag, webhook, mode] => {
+            Command::Add(tag.to_string(), webhook.to_string(), mode.to_lowercase())
+        }
+        ["remove", tag] => Command::Remove(tag.to_string()),
+        ["list"] => Command::List,
+        ["enable", tag] => Command::Enable(tag.to_string()),
+        ["disable", tag] => Command::Disable(tag.to_string()),
+        _ => Command::Nothing,
+    }
+}
+
+fn may_configure(c: &ChannelConfig, login: &str) -> bool {
+    !c.mods.is_empty() && (c.mods.iter().any(|m| *m == *login) || *login == c.channel)
+}
+
+// Whispers can select the channel with a leading "@channel",
+// otherwise the first channel the sender is allowed to configure is used.
+fn whisper_channel(bc: &BotConfig, login: &str, message_text: &str) -> Option<(String, String)> {
+    let (channel, text) = match message_text.trim().split_once(' ') {
+        Some((c, text)) if c.starts_with('@') => (c[1..].to_lowercase(), text.to_string()),
+        _ => (
+            bc.channels()
+                .find(|c| may_configure(c, login))?
+                .channel
+                .clone(),
+            message_text.to_string(),
+        ),
+    };
+    if may_configure(bc.channel(&channel)?, login) {
+        Some((channel, text))
     } else {
-        Whisper::Nothing
+        None
     }
 }
 
 fn join_tags(ts: &[Tag]) -> String {
     ts.iter()
-        .map(|t| t.tag.clone())
+        .map(|t| {
+            if t.enabled {
+                t.tag.clone()
+            } else {
+                format!("{} (disabled)", t.tag)
+            }
+        })
         .collect::<Vec<String>>()
         .join(", ")
 }
 
-fn sender_is_ignored(irc_bc: &Arc<RwLock<BotConfig>>, sender: &str) -> bool {
+fn sender_is_ignored(irc_bc: &Arc<RwLock<BotConfig>>, channel_login: &str, sender: &str) -> bool {
     irc_bc
         .read()
         .unwrap()
-        .ignore
+        .channel(channel_login)
+        .is_some_and(|c| {
+            c.ignore
+                .iter()
+                .any(|s| s.to_lowercase() == sender.to_lowercase())
+        })
+}
+
+// changes a copy of the tag and replaces the tag if the copy is valid
+fn update_tag<F>(c: &mut ChannelConfig, tag: &str, f: F) -> anyhow::Result<()>
+where
+    F: FnOnce(&mut Tag),
+{
+    let pos = c
+        .tags
         .iter()
-        .any(|s| s.to_lowercase() == sender.to_lowercase())
+        .position(|t| t.tag == tag)
+        .ok_or_else(|| anyhow::anyhow!("Unknown tag: {}", tag))?;
+    let mut new_tag = c.tags[pos].clone();
+    f(&mut new_tag);
+    new_tag.compile()?;
+    c.tags[pos] = new_tag;
+    Ok(())
+}
+
+enum Response {
+    // the config must be written
+    Changed(String),
+    Unchanged(String),
 }
 
+/// Applies the command to the channel, None if there is nothing to do.
+fn configure(c: &mut ChannelConfig, channel: &str, command: Command) -> Option<Response> {
+    let response = match command {
+        Command::Add(tag, webhook, match_mode) => {
+            let mut new_tag = crate::tag(tag.clone(), webhook);
+            new_tag.match_mode = match_mode;
+            if let Err(e) = new_tag.compile() {
+                info!("Tag rejected: {}", e);
+                return Some(Response::Unchanged(format!("Tag not added: {}", e)));
+            }
+            c.tags.push(new_tag);
+            info!("Tag added: {} ({})", &tag, &channel);
+            format!("Tag added: {}", tag)
+        }
+        Command::Remove(tag) => {
+            let tmp_tag = tag.clone();
+            if let Some(pos) = c.tags.iter().position(|x| *x.tag == tmp_tag) {
+                c.tags.remove(pos);
+            }
+            info!("Tag removed: {} ({})", &tag, &channel);
+            format!("Tag removed: {}", tag)
+        }
+        Command::List => {
+            info!("List Tags ({})", &channel);
+            return Some(Response::Unchanged(format!("Tags: {}", join_tags(&c.tags))));
+        }
+        Command::Describe(tag, description) => {
+            let response = format!("Description of {} changed", tag);
+            if let Err(e) = update_tag(c, &tag, |t| t.description = description) {
+                return Some(Response::Unchanged(format!(
+                    "Description not changed: {}",
+                    e
+                )));
+            }
+            info!("{} ({})", &response, &channel);
+            response
+        }
+        Command::Type(tag, channel_type) => {
+            if channel_type != "channel" && channel_type != "forum" {
+                return Some(Response::Unchanged(format!(
+                    "Unknown type {}, use channel or forum",
+                    channel_type
+                )));
+            }
+            let response = format!("Type of {} changed to {}", tag, channel_type);
+            if let Err(e) = update_tag(c, &tag, |t| t.channel_type = channel_type) {
+                return Some(Response::Unchanged(format!("Type not changed: {}", e)));
+            }
+            info!("{} ({})", &response, &channel);
+            response
+        }
+        Command::Rename(tag, new_tag) => {
+            if c.tags.iter().any(|t| t.tag == new_tag) {
+                return Some(Response::Unchanged(format!(
+                    "Tag {} exists already",
+                    new_tag
+                )));
+            }
+            let response = format!("Tag renamed: {} -> {}", tag, new_tag);
+            if let Err(e) = update_tag(c, &tag, |t| t.tag = new_tag) {
+                return Some(Response::Unchanged(format!("Tag not renamed: {}", e)));
+            }
+            info!("{} ({})", &response, &channel);
+            response
+        }
+        Command::Webhook(tag, webhook) => {
+            let response = format!("Webhook of {} replaced", tag);
+            if let Err(e) = update_tag(c, &tag, |t| t.webhook = webhook) {
+                return Some(Response::Unchanged(format!("Webhook not replaced: {}", e)));
+            }
+            info!("{} ({})", &response, &channel);
+            response
+        }
+        Command::AddMod(name) => {
+            if !c.mods.contains(&name) {
+                c.mods.push(name.clone());
+            }
+            info!("Mod added: {} ({})", &name, &channel);
+            format!("Mod added: {}", name)
+        }
+        Command::RemoveMod(name) => {
+            if c.mods.len() == 1 && c.mods[0] == name {
+                // nobody could configure the channel anymore
+                return Some(Response::Unchanged(
+                    "The last mod can't be removed".to_string(),
+                ));
+            }
+            c.mods.retain(|m| *m != name);
+            info!("Mod removed: {} ({})", &name, &channel);
+            format!("Mod removed: {}", name)
+        }
+        Command::ListMods => {
+            info!("List Mods ({})", &channel);
+            return Some(Response::Unchanged(format!("Mods: {}", c.mods.join(", "))));
+        }
+        Command::AddIgnore(name) => {
+            if !c.ignore.contains(&name) {
+                c.ignore.push(name.clone());
+            }
+            info!("Ignored: {} ({})", &name, &channel);
+            format!("Ignored: {}", name)
+        }
+        Command::RemoveIgnore(name) => {
+            c.ignore.retain(|i| i.to_lowercase() != name);
+            info!("No longer ignored: {} ({})", &name, &channel);
+            format!("No longer ignored: {}", name)
+        }
+        Command::ListIgnored => {
+            info!("List Ignored ({})", &channel);
+            return Some(Response::Unchanged(format!(
+                "Ignored: {}",
+                c.ignore.join(", ")
+            )));
+        }
+        Command::Enable(tag) => {
+            if let Err(e) = update_tag(c, &tag, |t| t.enabled = true) {
+                return Some(Response::Unchanged(format!("Tag not enabled: {}", e)));
+            }
+            info!("Tag enabled: {} ({})", &tag, &channel);
+            format!("Tag enabled: {}", tag)
+        }
+        Command::Disable(tag) => {
+            if let Err(e) = update_tag(c, &tag, |t| t.enabled = false) {
+                return Some(Response::Unchanged(format!("Tag not disabled: {}", e)));
+            }
+            info!("Tag disabled: {} ({})", &tag, &channel);
+            format!("Tag disabled: {}", tag)
+        }
+        Command::Nothing => return None,
+    };
+    Some(Response::Changed(response))
+}
+
+// The response and the configured channel, which is None if the sender may not configure the bot.
 fn handle_whisper(
     irc_bc: &Arc<RwLock<BotConfig>>,
-    login: String,
+    login: &str,
     message_text: String,
     config_file: &str,
-) -> Option<(String, String, String)> {
+) -> Option<(Option<String>, String)> {
     let mut bc = irc_bc.write().unwrap();
-    if !bc.mods.is_empty() && (bc.mods.iter().any(|m| *m == login) || login == bc.channel) {
-        match parse_whisper(&bc, &login, message_text) {
-            Whisper::Add(tag, webhook) => {
-                let new_tag = Tag {
-                    tag: tag.clone(),
-                    webhook,
-                    description: "".to_string(),
-                    channel_type: "channel".to_string(),
-                };
-                bc.tags.push(new_tag);
-                write_config_logged(config_file, &bc);
-                info!("Tag added: {}", &tag);
-                return Some((
-                    bc.channel.clone(),
-                    login.clone(),
-                    format!("Tag added: {}", tag),
-                ));
-            }
-            Whisper::Remove(tag) => {
-                let tmp_tag = tag.clone();
-                if let Some(pos) = bc.tags.iter().position(|x| *x.tag == tmp_tag) {
-                    bc.tags.remove(pos);
-                }
-                write_config_logged(config_file, &bc);
-                info!("Tag removed: {}", &tag);
-                return Some((
-                    bc.channel.clone(),
-                    login.clone(),
-                    format!("Tag removed: {}", tag),
-                ));
-            }
-            Whisper::List => {
-                info!("List Tags");
-                return Some((
-                    bc.channel.clone(),
-                    login.clone(),
-                    format!("Tags: {}", join_tags(&bc.tags)),
-                ));
-            }
-            Whisper::Nothing => {
+    if let Some((channel, message_text)) = whisper_channel(&bc, login, &message_text) {
+        let c = bc.channel_mut(&channel)?;
+        let response = match configure(c, &channel, parse_whisper(&message_text)) {
+            Some(Response::Changed(response)) => response,
+            Some(Response::Unchanged(response)) => return Some((Some(channel), response)),
+            None => {
                 info!("Mod-Whisper ignored");
                 return None;
             }
-        }
+        };
+        write_config_logged(config_file, &bc);
+        return Some((Some(channel), response));
     } else if !bc.whisper_response.is_empty() {
         return Some((
-            bc.channel.clone(),
-            login.clone(),
-            bc.whisper_response.clone(),
+            None,
+            template::render_text(&bc.whisper_response, &[("user", login)]),
         ));
     }
     None
 }
 
-async fn handle_message(
-    ircclient: &Arc<IRCClient>,
+fn handle_chat_command(
+    irc_bc: &Arc<RwLock<BotConfig>>,
+    channel: &str,
+    message_text: &str,
+    config_file: &str,
+) -> Option<String> {
+    let mut bc = irc_bc.write().unwrap();
+    let c = bc.channel_mut(channel)?;
+    match configure(c, channel, parse_chat_command(message_text)) {
+        Some(Response::Changed(response)) => {
+            write_config_logged(config_file, &bc);
+            Some(response)
+        }
+        Some(Response::Unchanged(response)) => Some(response),
+        None => Some(CHAT_COMMAND_USAGE.to_string()),
+    }
+}
+
+async fn handle_message<C: Output>(
+    ircclient: &C,
     config_file: &str,
     message: twitch_irc::message::ServerMessage,
     irc_bc: &Arc<RwLock<BotConfig>>,
-    activated: &mut bool,
+    cooldowns: &mut Cooldowns,
 ) {
     match message {
-        twitch_irc::message::ServerMessage::Privmsg(twitch_irc::message::PrivmsgMessage {
-            message_text,
-            message_id,
-            sender,
-            badges,
-            ..
-        }) => {
-            if sender_is_ignored(irc_bc, &sender.login) {
+        twitch_irc::message::ServerMessage::Privmsg(privmsg) => {
+            metrics::chat_message(&privmsg.channel_login);
+            status::message_received();
+            let message_text = privmsg.message_text.to_lowercase();
+            if sender_is_ignored(irc_bc, &privmsg.channel_login, &privmsg.sender.login) {
                 return;
-            } else if message_text.to_lowercase() == "#deactivate" && is_mod(&badges) {
-                info!("deactivated");
-                log_on_discord(irc_bc, "deactivated").await;
-                *activated = false;
-            } else if message_text.to_lowercase() == "#activate" && is_mod(&badges) {
-                *activated = true;
-                log_on_discord(irc_bc, "activated").await;
-                info!("activated");
-            } else if *activated {
-                send_messages(irc_bc, message_text, &sender, ircclient, message_id).await;
+            } else if message_text == "#deactivate" && is_mod(&privmsg.badges) {
+                info!(
+                    "deactivated by {} (#{})",
+                    privmsg.sender.login, privmsg.channel_login
+                );
+                status::set_active(&privmsg.channel_login, false);
+                log_on_discord(
+                    irc_bc,
+                    ircclient,
+                    &format!(
+                        "deactivated by {} (#{})",
+                        privmsg.sender.login, privmsg.channel_login
+                    ),
+                )
+                .await;
+            } else if message_text == "#activate" && is_mod(&privmsg.badges) {
+                status::set_active(&privmsg.channel_login, true);
+                log_on_discord(
+                    irc_bc,
+                    ircclient,
+                    &format!(
+                        "activated by {} (#{})",
+                        privmsg.sender.login, privmsg.channel_login
+                    ),
+                )
+                .await;
+                info!(
+                    "activated by {} (#{})",
+                    privmsg.sender.login, privmsg.channel_login
+                );
+            } else if message_text.split_whitespace().next() == Some("!askbot")
+                && is_mod(&privmsg.badges)
+            {
+                if let Some(response) = handle_chat_command(
+                    irc_bc,
+                    &privmsg.channel_login,
+                    &privmsg.message_text,
+                    config_file,
+                ) {
+                    log_on_discord(
+                        irc_bc,
+                        ircclient,
+                        &format!(
+                            "{} (#{}): {}",
+                            privmsg.sender.login, privmsg.channel_login, response
+                        ),
+                    )
+                    .await;
+                    ircclient
+                        .chat(
+                            privmsg.channel_login.clone(),
+                            response,
+                            Some(privmsg.message_id.clone()),
+                        )
+                        .await;
+                }
+            } else if status::is_active(&privmsg.channel_login) {
+                if status::take_new_stream(&privmsg.channel_login) {
+                    cooldowns.reset(&privmsg.channel_login);
+                }
+                send_messages(irc_bc, config_file, &privmsg, ircclient, cooldowns).await;
             }
         }
         twitch_irc::message::ServerMessage::Whisper(twitch_irc::message::WhisperMessage {
-            sender: twitch_irc::message::TwitchUserBasics { login, .. },
+            sender: twitch_irc::message::TwitchUserBasics { id, login, .. },
             message_text,
             ..
         }) => {
-            if let Some((c, u, m)) = handle_whisper(irc_bc, login, message_text, config_file) {
-                log_on_discord(irc_bc, &m).await;
-                privmsg(c, ircclient, format!("/w {} \"{}\"", u, m)).await;
+            metrics::whisper();
+            if let Some((c, m)) = handle_whisper(irc_bc, &login, message_text, config_file) {
+                // only the commands of mods are logged, anybody can whisper the bot
+                if let Some(c) = &c {
+                    log_on_discord(irc_bc, ircclient, &format!("{} (#{}): {}", login, c, m)).await;
+                }
+                let helix = helix::Helix::from_config(&irc_bc.read().unwrap());
+                ircclient.whisper(helix.as_ref(), c, login, id, m).await;
             }
         }
         _ => (),
     }
 }
 
-async fn log_on_discord(irc_bc: &Arc<RwLock<BotConfig>>, message: &str) {
+async fn log_on_discord<C: Output>(irc_bc: &Arc<RwLock<BotConfig>>, client: &C, message: &str) {
     let log_webhook = {
         let bc = irc_bc.write().unwrap();
         bc.log_webhook.clone()
     };
 
     if !log_webhook.is_empty() {
-        send_message(
-            &log_webhook,
-            "Askbot".to_string(),
-            message.to_string(),
-            false,
-        )
-        .await;
+        client
+            .post(&Delivery {
+                sink: sink::discord_string(),
+                webhook: log_webhook,
+                sender: "Askbot".to_string(),
+                text: message.to_string(),
+                forum: false,
+                thread_name: None,
+                embed: None,
+                avatar_url: None,
+            })
+            .await
+            .ok();
+    }
+}
+
+// log messages of the webfrontend, posted by a task of the main loop
+#[cfg(feature = "webfrontend")]
+static ANNOUNCEMENTS: std::sync::Mutex<Option<tokio::sync::mpsc::UnboundedSender<String>>> =
+    std::sync::Mutex::new(None);
+
+/// Posts the message to the log webhook, for callers outside of the async message handling.
+#[cfg(feature = "webfrontend")]
+fn announce(message: String) {
+    if let Some(tx) = ANNOUNCEMENTS.lock().unwrap().as_ref() {
+        tx.send(message).ok();
     }
 }
 
@@ -481,7 +1422,10 @@ fn create_default_config_file(path: &std::path::Path) -> anyhow::Result<()> {
             path,
             &BotConfig {
                 key: "askbot".to_string(),
-                use_reply: true,
+                primary: ChannelConfig {
+                    use_reply: true,
+                    ..std::default::Default::default()
+                },
                 ..std::default::Default::default()
             },
         )
@@ -503,6 +1447,14 @@ pub async fn main() -> anyhow::Result<()> {
         } else {
             config_file = args[1].to_string();
         }
+    } else if args.len() > 2 && args[1].to_lowercase() == "queue" {
+        return queue::cli(&args[2..]).await;
+    } else if args.len() > 2 && args[1].to_lowercase() == "history" {
+        return history::cli(&args[2..]);
+    } else if args.len() > 2 && args[1].to_lowercase() == "status" {
+        return status::cli(&args[2..]).await;
+    } else if args.len() >= 4 && args[1].to_lowercase() == "replay" {
+        return replay::replay(&args[2], &args[3], &args[4..]).await;
     }
     info!("Use config file: {:#?}", config_file);
 
@@ -534,23 +1486,71 @@ pub async fn main() -> anyhow::Result<()> {
                 Some(main_bc.read().unwrap().oauth_token.clone()),
             ));
 
-            let mut activated = true;
+            status::start(&config_file);
+            history::start(&config_file);
+            let mut cooldowns = Cooldowns::default();
 
             let (mut incoming_messages, ircclient) = IRCClient::new(config);
 
             let irc_client = Arc::new(ircclient);
             let irc_client_main = Arc::clone(&irc_client);
 
+            #[cfg(feature = "webfrontend")]
+            {
+                let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+                *ANNOUNCEMENTS.lock().unwrap() = Some(tx);
+                let log_bc = Arc::clone(&main_bc);
+                let log_client = Arc::clone(&irc_client);
+                tokio::spawn(async move {
+                    while let Some(message) = rx.recv().await {
+                        log_on_discord(&log_bc, log_client.as_ref(), &message).await;
+                    }
+                });
+            }
+
+            tokio::spawn(queue::retry_loop(config_file.clone()));
+            let (metrics_address, metrics_port) = {
+                let bc = main_bc.read().unwrap();
+                (metrics::address(&bc), bc.metrics_port)
+            };
+            if metrics_port != 0 {
+                tokio::spawn(metrics::serve(
+                    metrics_address,
+                    metrics_port,
+                    Arc::clone(&irc_client),
+                    Arc::clone(&main_bc),
+                    config_file.clone(),
+                ));
+            }
+            tokio::spawn(reload::watch(
+                config_file.clone(),
+                Arc::clone(&main_bc),
+                Arc::clone(&irc_client),
+            ));
+
             let join_handle = tokio::spawn(async move {
                 while let Some(message) = incoming_messages.recv().await {
-                    handle_message(&irc_client, &config_file, message, &irc_bc, &mut activated)
-                        .await;
+                    handle_message(
+                        irc_client.as_ref(),
+                        &config_file,
+                        message,
+                        &irc_bc,
+                        &mut cooldowns,
+                    )
+                    .await;
                 }
             });
 
-            let channel = main_bc.read().unwrap().channel.clone();
-            if let Err(e) = irc_client_main.join(channel.clone()) {
-                error!("Error: {}", e);
+            let channels = main_bc
+                .read()
+                .unwrap()
+                .channels()
+                .map(|c| c.channel.clone())
+                .collect::<Vec<String>>();
+            for channel in channels {
+                if let Err(e) = irc_client_main.join(channel.clone()) {
+                    error!("Error: {}", e);
+                }
             }
 
             if let Some(handle) = rocket_handle {
@@ -563,3 +1563,181 @@ pub async fn main() -> anyhow::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use twitch_irc::message::{IRCMessage, PrivmsgMessage};
+
+    fn compiled(name: &str, match_mode: &str) -> Tag {
+        let mut t = tag(name.to_string(), "http://w".to_string());
+        t.match_mode = match_mode.to_string();
+        t.compile().unwrap();
+        t
+    }
+
+    fn privmsg(badge_info: &str, badges: &str) -> PrivmsgMessage {
+        let line = format!(
+            "@badge-info={};badges={};id=1;room-id=0;user-id=0;display-name=bob;color=;emotes=;tmi-sent-ts=0 \
+             :bob!bob@bob.tmi.twitch.tv PRIVMSG #chan :#q hi",
+            badge_info, badges
+        );
+        PrivmsgMessage::try_from(IRCMessage::parse(&line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn literal_tags() {
+        let t = compiled("#Q", "literal");
+        assert!(t.matches("#q what?"));
+        assert!(t.matches("what? #q"));
+        assert!(!t.matches("#question what?"));
+        assert_eq!(t.strip("#Q  what  #q now"), "what now");
+    }
+
+    #[test]
+    fn glob_tags() {
+        assert_eq!(glob_to_regex("#q*"), "(?i)(^|\\s)\\#q\\S*(\\s|$)");
+        let t = compiled("#q*", "glob");
+        assert!(t.matches("#question what?"));
+        assert!(t.matches("what #q"));
+        assert!(!t.matches("what#q"));
+        assert_eq!(t.strip("#question what"), "what");
+        let t = compiled("#q?", "glob");
+        assert!(t.matches("#qa"));
+        assert!(!t.matches("#q a"));
+    }
+
+    #[test]
+    fn regex_tags() {
+        let t = compiled("#q(uestion)?\\b", "regex");
+        assert!(t.matches("a #question"));
+        assert!(t.matches("#q a"));
+        assert!(!t.matches("#quest"));
+        assert_eq!(t.strip("a #Question b"), "a b");
+
+        let mut t = tag("#q(".to_string(), "http://w".to_string());
+        t.match_mode = "regex".to_string();
+        let e = t.compile().unwrap_err().to_string();
+        assert!(e.starts_with("Invalid regex pattern \"#q(\": "), "{}", e);
+        assert!(!e.contains('\n'));
+
+        t.match_mode = "fuzzy".to_string();
+        let e = t.compile().unwrap_err().to_string();
+        assert_eq!(e, "Unknown match mode \"fuzzy\" of tag #q(");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        let mut t = tag("#q".to_string(), "http://w".to_string());
+        t.content_template = "{text".to_string();
+        let e = t.compile().unwrap_err().to_string();
+        assert_eq!(e, "Invalid template \"{text\": unclosed {text (tag #q)");
+        t.content_template = "{txt}".to_string();
+        assert!(t.compile().is_err());
+        // braces are text in the old fields
+        t.content_template = "".to_string();
+        t.description = "{text} :-{".to_string();
+        assert!(t.compile().is_ok());
+    }
+
+    #[test]
+    fn slack_tags() {
+        let mut t = tag("#q".to_string(), "http://w".to_string());
+        t.sink = "slack".to_string();
+        assert!(t.compile().is_ok());
+        t.channel_type = "forum".to_string();
+        let e = t.compile().unwrap_err().to_string();
+        assert_eq!(e, "Slack doesn't support forums or embeds (tag #q)");
+        t.channel_type = channel_string();
+        t.embed = true;
+        assert!(t.compile().is_err());
+        t.sink = "teams".to_string();
+        assert!(t.compile().is_err());
+    }
+
+    #[test]
+    fn mentions() {
+        assert_eq!(mention("!q @bob"), Some("@bob".to_string()));
+        assert_eq!(mention("!q bob"), None);
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(
+            split_args(r#"#describe #q "a question"  x"#),
+            vec!["#describe", "#q", "a question", "x"]
+        );
+        assert_eq!(split_args(r#"a "" b"#), vec!["a", "", "b"]);
+        assert_eq!(split_args(r#"a "b c"#), vec!["a", "b c"]);
+        assert_eq!(split_args("  "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            parse_whisper(r#"#describe #q "ask {user}""#),
+            Command::Describe("#q".to_string(), "ask {user}".to_string())
+        );
+        assert_eq!(
+            parse_whisper("#add #q* http://w GLOB"),
+            Command::Add(
+                "#q*".to_string(),
+                "http://w".to_string(),
+                "glob".to_string()
+            )
+        );
+        assert_eq!(
+            parse_whisper("#mod add Bob"),
+            Command::AddMod("bob".to_string())
+        );
+        assert_eq!(parse_whisper("#add #q"), Command::Nothing);
+        assert_eq!(
+            parse_chat_command("!askbot disable #q"),
+            Command::Disable("#q".to_string())
+        );
+        assert_eq!(parse_chat_command("!askbot"), Command::Nothing);
+    }
+
+    #[test]
+    fn badges() {
+        let mut t = compiled("#q", "literal");
+        assert!(t.permits(&privmsg("", "")));
+
+        t.required_badges = vec!["VIP".to_string(), "subscriber".to_string()];
+        assert!(!t.permits(&privmsg("", "")));
+        assert!(t.permits(&privmsg("", "vip/1")));
+        assert!(t.permits(&privmsg("", "broadcaster/1")));
+    }
+
+    #[test]
+    fn sub_months() {
+        let mut t = compiled("#q", "literal");
+        t.min_sub_months = 6;
+        assert!(!t.permits(&privmsg("", "")));
+        assert!(!t.permits(&privmsg("subscriber/5", "subscriber/3")));
+        assert!(t.permits(&privmsg("subscriber/6", "subscriber/6")));
+        assert!(t.permits(&privmsg("founder/12", "founder/0")));
+        assert!(t.permits(&privmsg("", "broadcaster/1")));
+    }
+
+    #[test]
+    fn titles() {
+        assert_eq!(strip_title("short"), "short");
+        let long = "word ".repeat(20);
+        assert_eq!(
+            strip_title(&long),
+            "word ".repeat(16).trim_end().to_string() + "..."
+        );
+        assert_eq!(strip_title(&"ä".repeat(80)), "ä".repeat(80));
+        assert_eq!(strip_title(&"ä".repeat(81)), "ä".repeat(80) + "...");
+        assert_eq!(
+            strip_title(&("€ ".repeat(50) + "🦀")),
+            "€ ".repeat(40).trim_end().to_string() + "..."
+        );
+        assert_eq!(
+            find_url("see https://youtu.be/x now"),
+            Some("https://youtu.be/x".to_string())
+        );
+        assert_eq!(find_url("no link"), None);
+    }
+}
@@ -0,0 +1,332 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{status, BotConfig, IRCClient};
+
+/// The metrics (and the status) are only reachable from the host by default.
+pub const METRICS_ADDRESS: &str = "127.0.0.1";
+
+// upper bounds of the webhook latency buckets in seconds
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+// the request line and headers, the body is ignored
+const MAX_REQUEST: usize = 8192;
+// slow or idle clients are disconnected
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+// name -> label values -> count
+type Counters = BTreeMap<&'static str, BTreeMap<Vec<(&'static str, String)>, u64>>;
+
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+struct Metrics {
+    counters: Counters,
+    // per channel
+    latency: BTreeMap<String, Histogram>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    counters: BTreeMap::new(),
+    latency: BTreeMap::new(),
+});
+
+// name, help and whether the counter has labels
+const COUNTERS: [(&str, &str, bool); 5] = [
+    ("askbot_chat_messages_total", "Chat messages seen", true),
+    (
+        "askbot_tag_matches_total",
+        "Chat messages matching a tag",
+        true,
+    ),
+    (
+        "askbot_webhook_posts_total",
+        "Webhook posts by result",
+        true,
+    ),
+    (
+        "askbot_chat_responses_total",
+        "Chat messages sent by the bot",
+        false,
+    ),
+    ("askbot_whispers_total", "Whispers handled", false),
+];
+
+fn inc(name: &'static str, labels: Vec<(&'static str, String)>) {
+    *METRICS
+        .lock()
+        .unwrap()
+        .counters
+        .entry(name)
+        .or_default()
+        .entry(labels)
+        .or_default() += 1;
+}
+
+pub fn chat_message(channel: &str) {
+    inc(
+        "askbot_chat_messages_total",
+        vec![("channel", channel.to_string())],
+    );
+}
+
+pub fn tag_match(channel: &str, tag: &str) {
+    inc(
+        "askbot_tag_matches_total",
+        vec![("channel", channel.to_string()), ("tag", tag.to_string())],
+    );
+}
+
+/// A post to the webhook of a tag (including retries of the queue).
+pub fn webhook_post(channel: &str, tag: &str, success: bool, latency: Duration) {
+    let result = if success { "success" } else { "failure" };
+    inc(
+        "askbot_webhook_posts_total",
+        vec![
+            ("channel", channel.to_string()),
+            ("tag", tag.to_string()),
+            ("result", result.to_string()),
+        ],
+    );
+    let seconds = latency.as_secs_f64();
+    let mut m = METRICS.lock().unwrap();
+    let latency = m
+        .latency
+        .entry(channel.to_string())
+        .or_insert_with(|| Histogram {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        });
+    for (i, le) in LATENCY_BUCKETS.iter().enumerate() {
+        if seconds <= *le {
+            latency.buckets[i] += 1;
+        }
+    }
+    latency.count += 1;
+    latency.sum += seconds;
+}
+
+pub fn chat_response() {
+    inc("askbot_chat_responses_total", vec![]);
+}
+
+pub fn whisper() {
+    inc("askbot_whispers_total", vec![]);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&str, String)]) -> String {
+    if labels.is_empty() {
+        return "".to_string();
+    }
+    let labels = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+        .collect::<Vec<String>>()
+        .join(",");
+    format!("{{{}}}", labels)
+}
+
+/// The prometheus text format.
+pub fn render(bc: &BotConfig) -> String {
+    let m = METRICS.lock().unwrap();
+    let mut out = String::new();
+    for (name, help, labelled) in COUNTERS {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        match m.counters.get(name) {
+            Some(values) => {
+                for (labels, count) in values {
+                    let _ = writeln!(out, "{}{} {}", name, format_labels(labels), count);
+                }
+            }
+            // counters without labels start at 0
+            None if !labelled => {
+                let _ = writeln!(out, "{} 0", name);
+            }
+            None => (),
+        }
+    }
+    let name = "askbot_webhook_duration_seconds";
+    let _ = writeln!(out, "# HELP {} Latency of the webhook posts", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (channel, latency) in &m.latency {
+        let bucket = |le: String| format_labels(&[("channel", channel.clone()), ("le", le)]);
+        for (le, count) in LATENCY_BUCKETS.iter().zip(latency.buckets.iter()) {
+            let _ = writeln!(out, "{}_bucket{} {}", name, bucket(le.to_string()), count);
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            bucket("+Inf".to_string()),
+            latency.count
+        );
+        let labels = format_labels(&[("channel", channel.clone())]);
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, latency.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, latency.count);
+    }
+    let name = "askbot_active";
+    let _ = writeln!(out, "# HELP {} Whether the bot is activated", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for c in bc.channels() {
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            name,
+            format_labels(&[("channel", c.channel.to_lowercase())]),
+            status::is_active(&c.channel) as u8
+        );
+    }
+    out
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    client: &IRCClient,
+    irc_bc: &Arc<RwLock<BotConfig>>,
+    config_file: &str,
+) -> std::io::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    let read = async {
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        Ok::<(), std::io::Error>(())
+    };
+    tokio::time::timeout(READ_TIMEOUT, read)
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "read timed out"))??;
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.split_whitespace();
+    let method = request_line.next();
+    let path = request_line
+        .next()
+        .and_then(|p| p.split('?').next())
+        .unwrap_or("");
+    let text = "text/plain; version=0.0.4";
+    let (code, content_type, body) = match (method, path) {
+        (Some("GET"), "/metrics") => ("200 OK", text, render(&irc_bc.read().unwrap())),
+        (Some("GET"), "/status") => {
+            let status = status::status(client, irc_bc, config_file).await;
+            let body = serde_json::to_string(&status).unwrap_or_default();
+            ("200 OK", "application/json", body)
+        }
+        (Some("GET"), _) => ("404 Not Found", text, "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            text,
+            "Method not allowed\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+pub fn address(bc: &BotConfig) -> String {
+    if bc.metrics_address.is_empty() {
+        METRICS_ADDRESS.to_string()
+    } else {
+        bc.metrics_address.clone()
+    }
+}
+
+/// Serves `/metrics` and `/status` on the port, independent of the webfrontend.
+pub async fn serve(
+    address: String,
+    port: u16,
+    client: Arc<IRCClient>,
+    irc_bc: Arc<RwLock<BotConfig>>,
+    config_file: String,
+) {
+    let listener = match TcpListener::bind((address.as_str(), port)).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Can't serve metrics on {}:{}: {}", address, port, e);
+            return;
+        }
+    };
+    info!("Serving metrics on {}:{}", address, port);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let client = Arc::clone(&client);
+                let irc_bc = Arc::clone(&irc_bc);
+                let config_file = config_file.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &client, &irc_bc, &config_file).await {
+                        debug!("Metrics request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => error!("Metrics: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rendered() {
+        let bc: BotConfig = serde_json::from_value(serde_json::json!({
+            "channel": "MetricsChan",
+            "tags": [],
+        }))
+        .unwrap();
+        webhook_post("metricschan", "#m", true, Duration::from_millis(250));
+        webhook_post("metricschan", "#m", false, Duration::from_secs(40));
+        tag_match("metrics\"chan", "#m");
+        let out = render(&bc);
+        for line in [
+            "askbot_webhook_posts_total{channel=\"metricschan\",tag=\"#m\",result=\"success\"} 1",
+            "askbot_webhook_posts_total{channel=\"metricschan\",tag=\"#m\",result=\"failure\"} 1",
+            "askbot_tag_matches_total{channel=\"metrics\\\"chan\",tag=\"#m\"} 1",
+            "askbot_webhook_duration_seconds_bucket{channel=\"metricschan\",le=\"0.1\"} 0",
+            "askbot_webhook_duration_seconds_bucket{channel=\"metricschan\",le=\"0.25\"} 1",
+            "askbot_webhook_duration_seconds_bucket{channel=\"metricschan\",le=\"30\"} 1",
+            "askbot_webhook_duration_seconds_bucket{channel=\"metricschan\",le=\"+Inf\"} 2",
+            "askbot_webhook_duration_seconds_sum{channel=\"metricschan\"} 40.25",
+            "askbot_webhook_duration_seconds_count{channel=\"metricschan\"} 2",
+            "# TYPE askbot_webhook_duration_seconds histogram",
+            "askbot_active{channel=\"metricschan\"} 1",
+        ] {
+            assert!(
+                out.lines().any(|l| l == line),
+                "{} missing in\n{}",
+                line,
+                out
+            );
+        }
+    }
+}
@@ -0,0 +1,400 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::sink::{send_message, Delivery, SendError};
+use crate::{history, is_zero, metrics, status};
+
+// seconds
+const RETRY_INTERVAL: u64 = 30;
+const BACKOFF_BASE: u64 = 30;
+const BACKOFF_MAX: u64 = 60 * 60;
+// an entry being sent is not sent by another task or process until the lease expires
+const LEASE: u64 = 5 * 60;
+
+/// Entries are dropped after this many failed attempts (about a day with the backoff).
+pub const MAX_ATTEMPTS: u32 = 30;
+
+// the queue file is shared between the message loop, the retry task and the webfrontend,
+// the lock file (e.g. `config.queue.lock`) also with the queue cli
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Entry {
+    pub id: u64,
+    // missing in queues of older versions
+    #[serde(default)]
+    pub channel: String,
+    pub tag: String,
+    #[serde(flatten)]
+    pub delivery: Delivery,
+    #[serde(default)]
+    pub attempts: u32,
+    #[serde(default)]
+    pub next_attempt: u64,
+    #[serde(default)]
+    pub last_error: String,
+    // the entry of the archive, marked as delivered on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub history_id: Option<i64>,
+    // unix time until which the entry is being sent
+    #[serde(skip_serializing_if = "is_zero")]
+    #[serde(default)]
+    pub leased_until: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct Queue {
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    entries: Vec<Entry>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn backoff(attempts: u32) -> u64 {
+    BACKOFF_BASE
+        .saturating_mul(2u64.saturating_pow(attempts))
+        .min(BACKOFF_MAX)
+}
+
+/// The queue is stored next to the config file, e.g. `config.queue.json`.
+pub fn queue_file(config_file: &str) -> String {
+    Path::new(config_file)
+        .with_extension("queue.json")
+        .to_string_lossy()
+        .to_string()
+}
+
+fn lock_file(queue_file: &str) -> anyhow::Result<File> {
+    let lock_file = Path::new(queue_file).with_extension("lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_file)?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+// a queue file that can't be read is left alone, it would be overwritten by the next save
+fn load(queue_file: &str) -> anyhow::Result<Queue> {
+    if !Path::new(queue_file).is_file() {
+        return Ok(Queue::default());
+    }
+    serde_any::from_file(queue_file)
+        .map_err(|e| anyhow::anyhow!("Can't read queue file {}: {}", queue_file, e))
+}
+
+// written next to the queue file and renamed, a crash doesn't leave a truncated queue
+fn save(queue_file: &str, q: &Queue) -> anyhow::Result<()> {
+    let tmp_file = Path::new(queue_file).with_extension("tmp.json");
+    serde_any::to_file_pretty(&tmp_file, q)
+        .map_err(|e| anyhow::anyhow!("Can't write queue file {}: {}", tmp_file.display(), e))?;
+    std::fs::rename(&tmp_file, queue_file)
+        .map_err(|e| anyhow::anyhow!("Can't write queue file {}: {}", queue_file, e))
+}
+
+fn modify<F, T>(queue_file: &str, f: F) -> anyhow::Result<T>
+where
+    F: FnOnce(&mut Queue) -> T,
+{
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    // released when dropped
+    let _file_lock = lock_file(queue_file)
+        .map_err(|e| error!("Can't lock queue file {}: {}", queue_file, e))
+        .ok();
+    let mut q = load(queue_file)?;
+    let result = f(&mut q);
+    save(queue_file, &q)?;
+    Ok(result)
+}
+
+// the locks block, e.g. while the cli sends an entry, and so does the file io
+async fn modify_blocking<F, T>(queue_file: &str, f: F) -> anyhow::Result<T>
+where
+    F: FnOnce(&mut Queue) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let queue_file = queue_file.to_string();
+    tokio::task::spawn_blocking(move || modify(&queue_file, f)).await?
+}
+
+pub fn entries(queue_file: &str) -> anyhow::Result<Vec<Entry>> {
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    let _file_lock = lock_file(queue_file)
+        .map_err(|e| error!("Can't lock queue file {}: {}", queue_file, e))
+        .ok();
+    Ok(load(queue_file)?.entries)
+}
+
+pub async fn push(
+    queue_file: &str,
+    channel: String,
+    tag: String,
+    delivery: Delivery,
+    error: String,
+    history_id: Option<i64>,
+) {
+    let result = modify_blocking(queue_file, move |q| {
+        let id = q.next_id;
+        q.next_id += 1;
+        q.entries.push(Entry {
+            id,
+            channel,
+            tag,
+            delivery,
+            attempts: 1,
+            next_attempt: now() + backoff(0),
+            last_error: error,
+            history_id,
+            leased_until: 0,
+        });
+        id
+    })
+    .await;
+    match result {
+        Ok(id) => info!("Queued failed message {} for retry", id),
+        Err(e) => error!("Can't queue failed message: {}", e),
+    }
+}
+
+/// Removes the entry, returns false if there is no such entry.
+pub fn drop_entry(queue_file: &str, id: u64) -> anyhow::Result<bool> {
+    modify(queue_file, |q| {
+        let len = q.entries.len();
+        q.entries.retain(|e| e.id != id);
+        len != q.entries.len()
+    })
+}
+
+/// Marks the entry as due, the retry task picks it up with the next run.
+#[cfg(feature = "webfrontend")]
+pub fn reschedule(queue_file: &str, id: u64) -> anyhow::Result<bool> {
+    modify(queue_file, |q| {
+        if let Some(e) = q.entries.iter_mut().find(|e| e.id == id) {
+            e.next_attempt = 0;
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// Queues a message of the archive again, due with the next run of the retry task.
+/// If it is still queued, it is only rescheduled.
+#[cfg(feature = "webfrontend")]
+pub fn resend(
+    queue_file: &str,
+    channel: String,
+    tag: String,
+    delivery: Delivery,
+    history_id: i64,
+) -> anyhow::Result<()> {
+    let id = modify(queue_file, |q| {
+        if let Some(e) = q
+            .entries
+            .iter_mut()
+            .find(|e| e.history_id == Some(history_id))
+        {
+            e.next_attempt = 0;
+            return e.id;
+        }
+        let id = q.next_id;
+        q.next_id += 1;
+        q.entries.push(Entry {
+            id,
+            channel,
+            tag,
+            delivery,
+            attempts: 0,
+            next_attempt: 0,
+            last_error: "".to_string(),
+            history_id: Some(history_id),
+            leased_until: 0,
+        });
+        id
+    })?;
+    info!("Queued message {} for resending", id);
+    Ok(())
+}
+
+// the first matching entry that isn't being sent by someone else, it is leased to the caller
+async fn claim<F>(queue_file: &str, f: F) -> anyhow::Result<Option<Entry>>
+where
+    F: Fn(&Entry) -> bool + Send + 'static,
+{
+    modify_blocking(queue_file, move |q| {
+        let e = q
+            .entries
+            .iter_mut()
+            .find(|e| f(e) && e.leased_until <= now())?;
+        e.leased_until = now() + LEASE;
+        Some(e.clone())
+    })
+    .await
+}
+
+// remove the entry on success or when giving up, otherwise back off
+async fn record_attempt(queue_file: &str, id: u64, result: Result<(), SendError>) {
+    let result = modify_blocking(queue_file, move |q| match result {
+        Ok(()) => q.entries.retain(|e| e.id != id),
+        Err(error) => {
+            if let Some(e) = q.entries.iter_mut().find(|e| e.id == id) {
+                e.next_attempt = now() + backoff(e.attempts);
+                e.attempts += 1;
+                e.last_error = error.message.clone();
+                e.leased_until = 0;
+            }
+            let give_up = |e: &Entry| e.id == id && (error.permanent || e.attempts >= MAX_ATTEMPTS);
+            if let Some(e) = q.entries.iter().find(|e| give_up(e)) {
+                error!(
+                    "Dropped queued message {} after {} attempt(s): {}",
+                    e.id, e.attempts, e.last_error
+                );
+            }
+            q.entries.retain(|e| !give_up(e));
+        }
+    })
+    .await;
+    if let Err(e) = result {
+        error!("Can't record the attempt of queued message {}: {}", id, e);
+    }
+}
+
+async fn retry(config_file: &str, entry: Entry) -> bool {
+    let start = Instant::now();
+    let result = send_message(&entry.delivery).await;
+    let success = result.is_ok();
+    metrics::webhook_post(&entry.channel, &entry.tag, success, start.elapsed());
+    if let Err(e) = &result {
+        status::webhook_error(&entry.tag, &e.message);
+    }
+    if success {
+        info!("Queued message {} delivered", entry.id);
+        if let Some(id) = entry.history_id {
+            history::set_status(history::history_file(config_file), id, history::DELIVERED).await;
+        }
+    }
+    record_attempt(&queue_file(config_file), entry.id, result).await;
+    success
+}
+
+pub async fn retry_due(config_file: &str) {
+    let queue_file = queue_file(config_file);
+    // the lock must not be held while sending
+    let file = queue_file.clone();
+    let due = match tokio::task::spawn_blocking(move || entries(&file)).await {
+        Ok(Ok(entries)) => entries
+            .into_iter()
+            .filter(|e| e.next_attempt <= now())
+            .map(|e| e.id)
+            .collect::<Vec<u64>>(),
+        Ok(Err(e)) => {
+            error!("Can't retry queued messages: {}", e);
+            return;
+        }
+        Err(e) => {
+            error!("Can't retry queued messages: {}", e);
+            return;
+        }
+    };
+    for id in due {
+        // e.g. dropped or sent by the cli in the meantime
+        match claim(&queue_file, move |e| e.id == id && e.next_attempt <= now()).await {
+            Ok(Some(entry)) => {
+                retry(config_file, entry).await;
+            }
+            Ok(None) => (),
+            Err(e) => error!("Can't retry queued message {}: {}", id, e),
+        }
+    }
+}
+
+pub async fn retry_loop(config_file: String) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(RETRY_INTERVAL)).await;
+        retry_due(&config_file).await;
+    }
+}
+
+/// askbot queue <config> [list | retry <id> | drop <id>]
+pub async fn cli(args: &[String]) -> anyhow::Result<()> {
+    let config_file = match args.first() {
+        Some(config_file) => config_file,
+        None => anyhow::bail!("usage: askbot queue <config> [list | retry <id> | drop <id>]"),
+    };
+    let queue_file = queue_file(config_file);
+    match args[1..].iter().map(|s| s.as_str()).collect::<Vec<&str>>()[..] {
+        [] | ["list"] => {
+            for e in entries(&queue_file)? {
+                println!(
+                    "{}\t{}\t{} attempt(s)\t{}: {}\t{}",
+                    e.id, e.tag, e.attempts, e.delivery.sender, e.delivery.text, e.last_error
+                );
+            }
+        }
+        ["retry", id] => {
+            let id = id.parse::<u64>()?;
+            if !entries(&queue_file)?.iter().any(|e| e.id == id) {
+                anyhow::bail!("No queued message with id {}", id);
+            }
+            match claim(&queue_file, move |e| e.id == id).await? {
+                Some(entry) => {
+                    if retry(config_file, entry).await {
+                        println!("delivered");
+                    } else {
+                        println!("failed again");
+                    }
+                }
+                None => anyhow::bail!("The message {} is being sent right now", id),
+            }
+        }
+        ["drop", id] => {
+            let id = id.parse::<u64>()?;
+            if !drop_entry(&queue_file, id)? {
+                anyhow::bail!("No queued message with id {}", id);
+            }
+        }
+        _ => anyhow::bail!("usage: askbot queue <config> [list | retry <id> | drop <id>]"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unreadable_queue_is_kept() {
+        let dir = std::env::temp_dir().join(format!("askbot-queue-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let queue_file = dir.join("config.queue.json").to_string_lossy().to_string();
+
+        assert_eq!(modify(&queue_file, |q| q.next_id).unwrap(), 0);
+        assert!(drop_entry(&queue_file, 0).is_ok_and(|dropped| !dropped));
+        assert!(!dir.join("config.queue.tmp.json").exists());
+
+        std::fs::write(&queue_file, "{\"next_id\": 3, \"entries\": [").unwrap();
+        assert!(modify(&queue_file, |q| q.next_id = 0).is_err());
+        assert!(entries(&queue_file).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&queue_file).unwrap(),
+            "{\"next_id\": 3, \"entries\": ["
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
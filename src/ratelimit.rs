@@ -0,0 +1,207 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often a message is re-sent after a 429 before giving up.
+pub const MAX_RETRIES: usize = 5;
+
+/// The longest wait for a rate limit in the message loop, longer limits fail
+/// the delivery and it is queued.
+pub const MAX_WAIT: Duration = Duration::from_secs(5);
+
+// longer retry_after values are not trusted
+const MAX_RETRY_AFTER: f64 = 600.0;
+
+// the limits are shared by all tags (and the log webhook)
+struct Limits {
+    global: Option<Instant>,
+    webhooks: BTreeMap<String, Instant>,
+}
+
+static LIMITS: Mutex<Limits> = Mutex::new(Limits {
+    global: None,
+    webhooks: BTreeMap::new(),
+});
+
+// body of a 429 response
+#[derive(Debug, Deserialize)]
+struct RateLimited {
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+fn header_secs(headers: &HeaderMap, name: &str) -> Option<f64> {
+    headers.get(name)?.to_str().ok()?.parse::<f64>().ok()
+}
+
+fn block(webhook: &str, secs: f64, global: bool) {
+    // "NaN" parses from a header too
+    let secs = if secs.is_nan() {
+        0.0
+    } else {
+        secs.clamp(0.0, MAX_RETRY_AFTER)
+    };
+    let until = Instant::now() + Duration::from_secs_f64(secs);
+    let mut limits = LIMITS.lock().unwrap();
+    if global {
+        limits.global = Some(limits.global.map_or(until, |g| g.max(until)));
+    } else {
+        limits.webhooks.insert(webhook.to_string(), until);
+    }
+}
+
+/// The time to wait before the webhook may be used again.
+pub fn delay(webhook: &str) -> Option<Duration> {
+    let limits = LIMITS.lock().unwrap();
+    let until = match (limits.global, limits.webhooks.get(webhook)) {
+        (Some(g), Some(w)) => g.max(*w),
+        (Some(g), None) => g,
+        (None, Some(w)) => *w,
+        (None, None) => return None,
+    };
+    until.checked_duration_since(Instant::now())
+}
+
+/// Waits for the rate limit of the webhook, unless it takes longer than `MAX_WAIT`.
+pub async fn wait(webhook: &str) -> Result<(), Duration> {
+    match delay(webhook) {
+        Some(d) if d > MAX_WAIT => Err(d),
+        Some(d) => {
+            debug!("Wait {:?} for the rate limit", d);
+            tokio::time::sleep(d).await;
+            Ok(())
+        }
+        None => Ok(()),
+    }
+}
+
+/// Remembers the reset time if the bucket of the webhook is exhausted.
+pub fn update(webhook: &str, headers: &HeaderMap) {
+    if header_secs(headers, "x-ratelimit-remaining") == Some(0.0) {
+        if let Some(reset_after) = header_secs(headers, "x-ratelimit-reset-after") {
+            block(webhook, reset_after, false);
+        }
+    }
+}
+
+// the seconds to wait and whether the limit is global, from the body or the headers of a 429
+fn retry_after(headers: &HeaderMap, body: &[u8]) -> (f64, bool) {
+    let global_header = headers
+        .get("x-ratelimit-global")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v == "true");
+    match serde_json::from_slice::<RateLimited>(body) {
+        Ok(r) => (r.retry_after, r.global || global_header),
+        Err(_) => (
+            header_secs(headers, "retry-after")
+                .or_else(|| header_secs(headers, "x-ratelimit-reset-after"))
+                .unwrap_or(1.0),
+            global_header,
+        ),
+    }
+}
+
+/// Handles a 429 response.
+pub async fn limited(webhook: &str, resp: reqwest::Response) {
+    let headers = resp.headers().clone();
+    let body = resp.bytes().await.unwrap_or_default();
+    let (retry_after, global) = retry_after(&headers, &body);
+    warn!(
+        "Rate limited ({}), retry after {}s",
+        if global { "global" } else { "webhook" },
+        retry_after
+    );
+    block(webhook, retry_after, global);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    // the global limit applies to all webhooks, the tests must not overlap
+    static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    fn secs(webhook: &str) -> f64 {
+        delay(webhook).map_or(0.0, |d| d.as_secs_f64())
+    }
+
+    #[test]
+    fn headers_and_bodies() {
+        let h = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset-after", "1.5"),
+            ("retry-after", "soon"),
+        ]);
+        assert_eq!(header_secs(&h, "x-ratelimit-remaining"), Some(0.0));
+        assert_eq!(header_secs(&h, "x-ratelimit-reset-after"), Some(1.5));
+        assert_eq!(header_secs(&h, "retry-after"), None);
+        assert_eq!(header_secs(&h, "x-missing"), None);
+
+        assert_eq!(retry_after(&h, br#"{"retry_after": 3.5}"#), (3.5, false));
+        assert_eq!(
+            retry_after(&h, br#"{"retry_after": 3.5, "global": true}"#),
+            (3.5, true)
+        );
+        // falls back to the headers
+        assert_eq!(retry_after(&h, b"<html>"), (1.5, false));
+        let h = headers(&[("retry-after", "7"), ("x-ratelimit-global", "true")]);
+        assert_eq!(retry_after(&h, b""), (7.0, true));
+        assert_eq!(retry_after(&HeaderMap::new(), b""), (1.0, false));
+    }
+
+    #[test]
+    fn clamped() {
+        let _serial = SERIAL.blocking_lock();
+        block("https://w/nan", f64::NAN, false);
+        assert_eq!(delay("https://w/nan"), None);
+        block("https://w/negative", -5.0, false);
+        assert_eq!(delay("https://w/negative"), None);
+        block("https://w/long", 1e9, false);
+        let d = secs("https://w/long");
+        assert!(d > MAX_RETRY_AFTER - 1.0 && d <= MAX_RETRY_AFTER, "{}", d);
+    }
+
+    #[tokio::test]
+    async fn buckets() {
+        let _serial = SERIAL.lock().await;
+        update("https://w/a", &headers(&[("x-ratelimit-remaining", "1")]));
+        assert_eq!(delay("https://w/a"), None);
+        update(
+            "https://w/a",
+            &headers(&[
+                ("x-ratelimit-remaining", "0"),
+                ("x-ratelimit-reset-after", "60"),
+            ]),
+        );
+        assert!(secs("https://w/a") > 59.0);
+        assert_eq!(delay("https://w/b"), None);
+        assert_eq!(wait("https://w/b").await, Ok(()));
+        assert!(wait("https://w/a").await.is_err_and(|d| d > MAX_WAIT));
+
+        // a global limit holds back every webhook, a shorter one doesn't shorten it
+        block("https://w/b", 0.2, true);
+        block("https://w/c", 0.1, true);
+        let d = secs("https://w/c");
+        assert!(d > 0.1 && d <= 0.2, "{}", d);
+        assert!(secs("https://w/a") > 59.0);
+        let start = Instant::now();
+        assert_eq!(wait("https://w/c").await, Ok(()));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(delay("https://w/c"), None);
+    }
+}
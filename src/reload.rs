@@ -0,0 +1,228 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::{log_on_discord, read_config, BotConfig, ChannelConfig, IRCClient};
+
+// seconds between two checks of the config file
+const POLL_INTERVAL: u64 = 2;
+
+// the irc connection and the metrics server are not re-established
+const NEEDS_RESTART: [&str; 4] = ["username", "oauth_token", "metrics_port", "metrics_address"];
+
+// modification time and size, they change between two polls while the file is written
+fn stamp(config_file: &str) -> Option<(SystemTime, u64)> {
+    let metadata = std::fs::metadata(config_file).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn to_value<T: serde::Serialize>(t: &T) -> Value {
+    serde_json::to_value(t).unwrap_or_default()
+}
+
+// top level keys of two json objects with different values
+fn changed_keys(old: &Value, new: &Value, skip: &[&str]) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let old = old.as_object().unwrap_or(&empty);
+    let new = new.as_object().unwrap_or(&empty);
+    let mut keys = old
+        .keys()
+        .chain(new.keys().filter(|k| !old.contains_key(*k)))
+        .filter(|k| !skip.contains(&k.as_str()) && old.get(*k) != new.get(*k))
+        .cloned()
+        .collect::<Vec<String>>();
+    keys.sort();
+    keys
+}
+
+fn channel_changes(old: &ChannelConfig, new: &ChannelConfig) -> Vec<String> {
+    let name = &new.channel;
+    let mut changes = vec![];
+    for t in new.tags.iter() {
+        match old.tags.iter().find(|o| o.tag == t.tag) {
+            None => changes.push(format!("#{}: tag added: {}", name, t.tag)),
+            Some(o) if to_value(o) != to_value(t) => {
+                changes.push(format!("#{}: tag changed: {}", name, t.tag))
+            }
+            Some(_) => (),
+        }
+    }
+    for t in old.tags.iter() {
+        if !new.tags.iter().any(|n| n.tag == t.tag) {
+            changes.push(format!("#{}: tag removed: {}", name, t.tag));
+        }
+    }
+    for key in changed_keys(&to_value(old), &to_value(new), &["channel", "tags"]) {
+        changes.push(format!("#{}: {} changed", name, key));
+    }
+    changes
+}
+
+/// A summary of the differences, secrets are not included.
+pub fn config_changes(old: &BotConfig, new: &BotConfig) -> Vec<String> {
+    let mut changes = vec![];
+    for c in new.channels() {
+        match old.channel(&c.channel) {
+            Some(o) => changes.append(&mut channel_changes(o, c)),
+            None => changes.push(format!("channel added: #{}", c.channel)),
+        }
+    }
+    for c in old.channels() {
+        if new.channel(&c.channel).is_none() {
+            changes.push(format!("channel removed: #{}", c.channel));
+        }
+    }
+    // only the settings of the bot, the channels are compared above
+    let settings = |bc: &BotConfig| {
+        to_value(&BotConfig {
+            primary: ChannelConfig::default(),
+            channels: vec![],
+            ..bc.clone()
+        })
+    };
+    for key in changed_keys(&settings(old), &settings(new), &[]) {
+        if NEEDS_RESTART.contains(&key.as_str()) {
+            changes.push(format!("{} changed (needs a restart)", key));
+        } else {
+            changes.push(format!("{} changed", key));
+        }
+    }
+    changes
+}
+
+fn channel_logins(bc: &BotConfig) -> HashSet<String> {
+    bc.channels().map(|c| c.channel.to_lowercase()).collect()
+}
+
+async fn reload(config_file: &str, irc_bc: &Arc<RwLock<BotConfig>>, client: &IRCClient) {
+    let new = match read_config(config_file) {
+        Ok(bc) => bc,
+        Err(e) => {
+            error!("Config not reloaded: {}", e);
+            log_on_discord(irc_bc, client, &format!("Config not reloaded: {}", e)).await;
+            return;
+        }
+    };
+    let changes = {
+        let mut bc = irc_bc.write().unwrap();
+        let changes = config_changes(&bc, &new);
+        if changes.is_empty() {
+            // e.g. written by the bot itself
+            return;
+        }
+        if channel_logins(&bc) != channel_logins(&new) {
+            if let Err(e) = client.set_wanted_channels(channel_logins(&new)) {
+                error!("Config not reloaded: {}", e);
+                return;
+            }
+        }
+        *bc = new;
+        changes
+    };
+    info!("Config reloaded: {}", changes.join(", "));
+    log_on_discord(
+        irc_bc,
+        client,
+        &format!("Config reloaded:\n{}", changes.join("\n")),
+    )
+    .await;
+}
+
+/// Reloads the config file whenever it is modified.
+pub async fn watch(config_file: String, irc_bc: Arc<RwLock<BotConfig>>, client: Arc<IRCClient>) {
+    let mut loaded = stamp(&config_file);
+    let mut last = loaded;
+    loop {
+        tokio::time::sleep(Duration::from_secs(POLL_INTERVAL)).await;
+        let s = stamp(&config_file);
+        // read once it didn't change since the last poll, not while it is half written
+        if s.is_some() && s != loaded && s == last {
+            loaded = s;
+            reload(&config_file, &irc_bc, client.as_ref()).await;
+        }
+        last = s;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn config(value: Value) -> BotConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn keys() {
+        let old = json!({"a": 1, "b": [1], "c": "x", "skip": 1});
+        let new = json!({"a": 1, "b": [2], "d": true, "skip": 2});
+        assert_eq!(changed_keys(&old, &new, &["skip"]), vec!["b", "c", "d"]);
+        assert!(changed_keys(&old, &old, &[]).is_empty());
+        assert_eq!(changed_keys(&json!(null), &json!({"a": 1}), &[]), vec!["a"]);
+    }
+
+    #[test]
+    fn channels() {
+        let old: ChannelConfig = serde_json::from_value(json!({
+            "channel": "chan",
+            "tags": [{"tag": "#a", "webhook": "w"}, {"tag": "#b", "webhook": "w"}],
+            "mods": ["bob"]
+        }))
+        .unwrap();
+        let new: ChannelConfig = serde_json::from_value(json!({
+            "channel": "chan",
+            "tags": [{"tag": "#a", "webhook": "w2"}, {"tag": "#c", "webhook": "w"}],
+            "mods": ["bob", "ann"],
+            "use_reply": false
+        }))
+        .unwrap();
+        assert_eq!(
+            channel_changes(&old, &new),
+            vec![
+                "#chan: tag changed: #a",
+                "#chan: tag added: #c",
+                "#chan: tag removed: #b",
+                "#chan: mods changed",
+                "#chan: use_reply changed",
+            ]
+        );
+        assert!(channel_changes(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn configs() {
+        let old = config(json!({
+            "channel": "chan",
+            "username": "bot",
+            "oauth_token": "oauth:a",
+            "key": "k",
+            "channels": [{"channel": "two"}]
+        }));
+        let new = config(json!({
+            "channel": "chan",
+            "mods": ["bob"],
+            "username": "bot",
+            "oauth_token": "oauth:b",
+            "key": "k2",
+            "log_webhook": "w",
+            "channels": [{"channel": "three"}]
+        }));
+        assert_eq!(
+            config_changes(&old, &new),
+            vec![
+                "#chan: mods changed",
+                "channel added: #three",
+                "channel removed: #two",
+                "key changed",
+                "log_webhook changed",
+                "oauth_token changed (needs a restart)",
+            ]
+        );
+        assert!(config_changes(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex, RwLock};
+use twitch_irc::message::{IRCMessage, IRCPrefix, IRCTags, ServerMessage};
+
+use crate::cooldown::Cooldowns;
+use crate::helix::Helix;
+use crate::sink::{is_discord_string, Delivery, SendError};
+use crate::{handle_message, read_config, strip_title, BotConfig, Output};
+
+const USAGE: &str = "usage: askbot replay <config> <chatlog> [--fail <tag|webhook>]...";
+
+// one line of a json-lines chat export
+#[derive(Debug, Deserialize)]
+struct ChatLine {
+    channel: String,
+    user: String,
+    message: String,
+    #[serde(default)]
+    display_name: String,
+    // e.g. "moderator/1,subscriber/12"
+    #[serde(default)]
+    badges: String,
+    #[serde(default)]
+    badge_info: String,
+    #[serde(default)]
+    color: String,
+    #[serde(default)]
+    id: String,
+    // milliseconds since the epoch
+    #[serde(default)]
+    timestamp: i64,
+}
+
+impl ChatLine {
+    fn into_irc(self, line_number: usize) -> IRCMessage {
+        let mut tags = IRCTags::new();
+        let mut tag = |k: &str, v: String| tags.0.insert(k.to_string(), Some(v));
+        tag("room-id", "0".to_string());
+        tag("user-id", "0".to_string());
+        tag(
+            "display-name",
+            if self.display_name.is_empty() {
+                self.user.clone()
+            } else {
+                self.display_name
+            },
+        );
+        tag("badges", self.badges);
+        tag("badge-info", self.badge_info);
+        tag("color", self.color);
+        tag("emotes", "".to_string());
+        tag(
+            "id",
+            if self.id.is_empty() {
+                format!("replay-{}", line_number)
+            } else {
+                self.id
+            },
+        );
+        tag("tmi-sent-ts", self.timestamp.to_string());
+        IRCMessage::new(
+            tags,
+            Some(IRCPrefix::Full {
+                nick: self.user.clone(),
+                user: Some(self.user.clone()),
+                host: Some(format!("{}.tmi.twitch.tv", self.user)),
+            }),
+            "PRIVMSG".to_string(),
+            vec![
+                format!("#{}", self.channel.trim_start_matches('#')),
+                self.message,
+            ],
+        )
+    }
+}
+
+fn parse_line(line: &str, line_number: usize) -> anyhow::Result<ServerMessage> {
+    let irc = if line.starts_with('{') {
+        serde_json::from_str::<ChatLine>(line)?.into_irc(line_number)
+    } else {
+        IRCMessage::parse(line)?
+    };
+    Ok(ServerMessage::try_from(irc)?)
+}
+
+/// Collects what the bot would have sent.
+#[derive(Default)]
+struct Recorder {
+    webhooks: Vec<(String, String)>,
+    // posts to these webhooks fail
+    failing: Vec<String>,
+    events: Mutex<Vec<String>>,
+    posts: Mutex<usize>,
+    chats: Mutex<usize>,
+}
+
+impl Recorder {
+    fn record(&self, event: String) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn webhook_name(&self, webhook: &str) -> String {
+        match self.webhooks.iter().find(|(w, _)| w == webhook) {
+            Some((_, name)) => name.clone(),
+            None => webhook.to_string(),
+        }
+    }
+}
+
+impl Output for Recorder {
+    async fn chat(&self, channel: String, msg: String, reply_to: Option<String>) {
+        *self.chats.lock().unwrap() += 1;
+        if reply_to.is_some() {
+            self.record(format!("reply in #{}: {}", channel, msg));
+        } else {
+            self.record(format!("say in #{}: {}", channel, msg));
+        }
+    }
+
+    async fn whisper(
+        &self,
+        _helix: Option<&Helix>,
+        _channel: Option<String>,
+        user: String,
+        _user_id: String,
+        msg: String,
+    ) {
+        self.record(format!("whisper to {}: {}", user, msg));
+    }
+
+    async fn post(&self, delivery: &Delivery) -> Result<(), SendError> {
+        *self.posts.lock().unwrap() += 1;
+        let failing = self.failing.contains(&delivery.webhook);
+        self.record(format!(
+            "post to {}{}{}{}{}: {}: {}",
+            self.webhook_name(&delivery.webhook),
+            if failing { " failed (simulated)" } else { "" },
+            match (delivery.forum, &delivery.thread_name) {
+                (true, Some(name)) => format!(" (forum: {})", name),
+                (true, None) => " (forum)".to_string(),
+                _ => "".to_string(),
+            },
+            if delivery.embed.is_some() {
+                " (embed)"
+            } else {
+                ""
+            },
+            if is_discord_string(&delivery.sink) {
+                "".to_string()
+            } else {
+                format!(" ({})", delivery.sink)
+            },
+            delivery.sender,
+            delivery.text
+        ));
+        if failing {
+            return Err(SendError {
+                message: "simulated failure".to_string(),
+                permanent: false,
+            });
+        }
+        Ok(())
+    }
+
+    // without the youtube lookup
+    async fn thread_name(&self, text: &str) -> String {
+        strip_title(text)
+    }
+}
+
+/// askbot replay <config> <chatlog> [--fail <tag|webhook>]...
+///
+/// Feeds a chat log (raw irc lines or json lines) through the message handling
+/// and prints the webhook posts and chat messages instead of sending them.
+/// Posts to the webhooks given with `--fail` fail, e.g. to check the failure responses.
+pub async fn replay(config_file: &str, chatlog: &str, args: &[String]) -> anyhow::Result<()> {
+    let mut bc = read_config(config_file)?;
+    // commands in the chat log must not change the real config file
+    let scratch_file = std::env::temp_dir()
+        .join(format!("askbot-replay-{}.json", std::process::id()))
+        .to_string_lossy()
+        .to_string();
+    // no twitch api lookups
+    bc.client_id.clear();
+    let mut webhooks = vec![];
+    for c in bc.channels() {
+        for t in c.tags.iter() {
+            webhooks.push((t.webhook.clone(), format!("{} ({})", t.tag, c.channel)));
+        }
+    }
+    webhooks.push((bc.log_webhook.clone(), "log".to_string()));
+    let mut failing = vec![];
+    for arg in args.chunks(2) {
+        match arg {
+            [flag, fail] if flag == "--fail" => {
+                let tags = bc
+                    .channels()
+                    .flat_map(|c| c.tags.iter())
+                    .filter(|t| t.tag.eq_ignore_ascii_case(fail))
+                    .map(|t| t.webhook.clone())
+                    .collect::<Vec<String>>();
+                if tags.is_empty() {
+                    failing.push(fail.clone());
+                } else {
+                    failing.extend(tags);
+                }
+            }
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    let recorder = Recorder {
+        webhooks,
+        failing,
+        ..Default::default()
+    };
+    let irc_bc: Arc<RwLock<BotConfig>> = Arc::new(RwLock::new(bc));
+    let mut cooldowns = Cooldowns::default();
+    let mut messages = 0;
+    let mut skipped = 0;
+
+    for (i, line) in std::fs::read_to_string(chatlog)?.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let message = match parse_line(line, i + 1) {
+            Ok(ServerMessage::Privmsg(m)) => m,
+            Ok(_) => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => {
+                println!("line {}: can't parse: {}", i + 1, e);
+                skipped += 1;
+                continue;
+            }
+        };
+        messages += 1;
+        println!(
+            "#{} {}: {}",
+            message.channel_login, message.sender.login, message.message_text
+        );
+        handle_message(
+            &recorder,
+            &scratch_file,
+            ServerMessage::Privmsg(message),
+            &irc_bc,
+            &mut cooldowns,
+        )
+        .await;
+        for event in recorder.events.lock().unwrap().drain(..) {
+            println!("    -> {}", event);
+        }
+    }
+
+    std::fs::remove_file(&scratch_file).ok();
+    std::fs::remove_file(crate::history::history_file(&scratch_file)).ok();
+    // failed posts are queued
+    let queue_file = crate::queue::queue_file(&scratch_file);
+    std::fs::remove_file(&queue_file).ok();
+    std::fs::remove_file(std::path::Path::new(&queue_file).with_extension("lock")).ok();
+
+    println!();
+    println!("chat messages:  {}", messages);
+    println!("skipped lines:  {}", skipped);
+    println!("webhook posts:  {}", recorder.posts.lock().unwrap());
+    println!("chat responses: {}", recorder.chats.lock().unwrap());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use twitch_irc::message::PrivmsgMessage;
+
+    fn privmsg(line: &str) -> PrivmsgMessage {
+        match parse_line(line, 7).unwrap() {
+            ServerMessage::Privmsg(privmsg) => privmsg,
+            m => panic!("not a privmsg: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let p = privmsg(
+            r##"{"channel": "#chan", "user": "bob", "message": "#q hi", "display_name": "Bob",
+            "badges": "moderator/1,subscriber/12", "badge_info": "subscriber/14",
+            "color": "#FF8000", "id": "abc", "timestamp": 1700000000000}"##,
+        );
+        assert_eq!(p.channel_login, "chan");
+        assert_eq!(p.sender.login, "bob");
+        assert_eq!(p.sender.name, "Bob");
+        assert_eq!(p.message_text, "#q hi");
+        assert_eq!(p.message_id, "abc");
+        assert_eq!(p.badges.len(), 2);
+        assert_eq!(p.badges[1].name, "subscriber");
+        assert_eq!(p.badge_info[0].version, "14");
+        let color = p.name_color.unwrap();
+        assert_eq!((color.r, color.g, color.b), (0xff, 0x80, 0));
+        assert_eq!(p.server_timestamp.timestamp(), 1700000000);
+
+        // only channel, user and message are required
+        let p = privmsg(r#"{"channel": "chan", "user": "ann", "message": "hi"}"#);
+        assert_eq!(p.sender.name, "ann");
+        assert_eq!(p.message_id, "replay-7");
+        assert!(p.badges.is_empty());
+        assert!(p.name_color.is_none());
+
+        assert!(parse_line(r#"{"channel": "chan", "user": "ann"}"#, 7).is_err());
+        assert!(parse_line("{not json", 7).is_err());
+    }
+
+    #[test]
+    fn irc_lines() {
+        let p = privmsg(
+            "@badge-info=;badges=vip/1;color=;display-name=Bob;emotes=;id=abc;room-id=1;\
+             tmi-sent-ts=1700000000000;user-id=2 :bob!bob@bob.tmi.twitch.tv PRIVMSG #chan :#q hi",
+        );
+        assert_eq!(p.channel_login, "chan");
+        assert_eq!(p.sender.name, "Bob");
+        assert_eq!(p.message_text, "#q hi");
+        assert_eq!(p.badges[0].name, "vip");
+        assert!(matches!(
+            parse_line("PING :tmi.twitch.tv", 1).unwrap(),
+            ServerMessage::Ping(_)
+        ));
+        // a privmsg without the twitch tags
+        assert!(parse_line(":bob!bob@bob PRIVMSG #chan :hi", 1).is_err());
+        assert!(parse_line("", 1).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{ratelimit, thread_name};
+
+/// The known sink types of a tag.
+pub const SINKS: [&str; 2] = ["discord", "slack"];
+
+pub fn is_discord_string(str: &str) -> bool {
+    str == "discord"
+}
+
+pub fn discord_string() -> String {
+    "discord".to_string()
+}
+
+/// A message for the webhook of a tag.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Delivery {
+    #[serde(skip_serializing_if = "is_discord_string")]
+    #[serde(default = "discord_string")]
+    pub sink: String,
+    pub webhook: String,
+    pub sender: String,
+    pub text: String,
+    #[serde(default)]
+    pub forum: bool,
+    // the name of the forum thread, generated from the text if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub thread_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub embed: Option<Embed>,
+    // the profile picture of the sender
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+/// Details of the chat message for tags with `embed`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Embed {
+    pub display_name: String,
+    // the twitch name colour as 0xRRGGBB
+    #[serde(default)]
+    pub color: Option<u32>,
+    // e.g. "subscriber/12"
+    #[serde(default)]
+    pub badges: Vec<String>,
+    // rfc 3339
+    pub timestamp: String,
+    pub channel: String,
+    pub tag: String,
+}
+
+/// Why a post failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SendError {
+    pub message: String,
+    // e.g. a deleted webhook or an invalid payload, sending it again won't help
+    pub permanent: bool,
+}
+
+impl SendError {
+    fn temporary(message: String) -> Self {
+        SendError {
+            message,
+            permanent: false,
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+// 4xx answers are not retried, except for timeouts and rate limits
+fn is_permanent(status: StatusCode) -> bool {
+    status.is_client_error()
+        && status != StatusCode::REQUEST_TIMEOUT
+        && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+/// A webhook service, it decides the payload and when a post was accepted.
+/// The rate limit handling is shared by all sinks.
+pub trait Sink {
+    async fn payload(&self, delivery: &Delivery) -> serde_json::Value;
+    fn accepted(&self, status: StatusCode, body: &str) -> Result<(), String>;
+}
+
+pub struct Discord;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Msg {
+    username: String,
+    avatar_url: Option<String>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    content: String,
+    thread_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    embeds: Vec<DiscordEmbed>,
+}
+
+fn msg(
+    username: String,
+    avatar_url: Option<String>,
+    content: String,
+    thread_name: Option<String>,
+) -> Msg {
+    Msg {
+        username,
+        avatar_url,
+        content,
+        thread_name,
+        embeds: vec![],
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct DiscordEmbed {
+    author: EmbedAuthor,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<u32>,
+    timestamp: String,
+    footer: EmbedFooter,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<EmbedField>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct EmbedAuthor {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct EmbedFooter {
+    text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct EmbedField {
+    name: String,
+    value: String,
+    inline: bool,
+}
+
+fn discord_embed(text: &str, embed: &Embed) -> DiscordEmbed {
+    let mut fields = vec![];
+    if !embed.badges.is_empty() {
+        fields.push(EmbedField {
+            name: "Badges".to_string(),
+            value: embed.badges.join(", "),
+            inline: true,
+        });
+    }
+    DiscordEmbed {
+        author: EmbedAuthor {
+            name: embed.display_name.clone(),
+        },
+        description: text.to_string(),
+        color: embed.color,
+        timestamp: embed.timestamp.clone(),
+        footer: EmbedFooter {
+            text: format!("#{} · {}", embed.channel, embed.tag),
+        },
+        fields,
+    }
+}
+
+impl Sink for Discord {
+    async fn payload(&self, delivery: &Delivery) -> serde_json::Value {
+        let thread_name = if delivery.forum {
+            match &delivery.thread_name {
+                Some(name) => Some(name.clone()),
+                None => Some(thread_name(&delivery.text).await),
+            }
+        } else {
+            None
+        };
+        let username = delivery.sender.clone();
+        let avatar_url = delivery.avatar_url.clone();
+        let message = match &delivery.embed {
+            Some(embed) => Msg {
+                embeds: vec![discord_embed(&delivery.text, embed)],
+                ..msg(username, avatar_url, "".to_string(), thread_name)
+            },
+            None => msg(username, avatar_url, delivery.text.clone(), thread_name),
+        };
+        serde_json::to_value(message).unwrap_or_default()
+    }
+
+    fn accepted(&self, status: StatusCode, _body: &str) -> Result<(), String> {
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Code: {} Reason: {:?}",
+                status,
+                status.canonical_reason()
+            ))
+        }
+    }
+}
+
+/// Slack incoming webhooks, forums and embeds are not supported.
+pub struct Slack;
+
+// "&", "<" and ">" are control characters in slack messages
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl Sink for Slack {
+    async fn payload(&self, delivery: &Delivery) -> serde_json::Value {
+        // the username of app webhooks can't be overridden
+        serde_json::json!({
+            "text": format!(
+                "*{}*: {}",
+                slack_escape(&delivery.sender),
+                slack_escape(&delivery.text)
+            )
+        })
+    }
+
+    // slack answers with "ok" or with an error code like "invalid_payload"
+    fn accepted(&self, status: StatusCode, body: &str) -> Result<(), String> {
+        if status == StatusCode::OK && body.trim() == "ok" {
+            Ok(())
+        } else {
+            Err(format!("Code: {} Error: {}", status, body.trim()))
+        }
+    }
+}
+
+async fn deliver<S: Sink>(sink: &S, delivery: &Delivery) -> Result<(), SendError> {
+    let client = reqwest::Client::new();
+    let webhook = delivery.webhook.as_str();
+    let payload = sink.payload(delivery).await;
+    for _ in 0..ratelimit::MAX_RETRIES {
+        if let Err(d) = ratelimit::wait(webhook).await {
+            error!("Error: rate limited for {}s", d.as_secs());
+            return Err(SendError::temporary(format!(
+                "rate limited for {}s",
+                d.as_secs()
+            )));
+        }
+        match client.post(webhook).json(&payload).send().await {
+            Ok(resp) => {
+                ratelimit::update(webhook, resp.headers());
+                if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+                    ratelimit::limited(webhook, resp).await;
+                    continue;
+                }
+                let status = resp.status();
+                let body = resp.text().await.unwrap_or_default();
+                return sink.accepted(status, &body).map_err(|e| {
+                    error!("Error: {}", e);
+                    SendError {
+                        message: e,
+                        permanent: is_permanent(status),
+                    }
+                });
+            }
+            Err(e) => {
+                // the url of the webhook contains its token
+                let e = e.without_url();
+                error!("Error: {}", e);
+                return Err(SendError::temporary(e.to_string()));
+            }
+        }
+    }
+    error!(
+        "Error: still rate limited after {} retries",
+        ratelimit::MAX_RETRIES
+    );
+    Err(SendError::temporary("rate limited".to_string()))
+}
+
+pub async fn send_message(delivery: &Delivery) -> Result<(), SendError> {
+    match delivery.sink.as_str() {
+        "slack" => deliver(&Slack, delivery).await,
+        _ => deliver(&Discord, delivery).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn delivery(sink: &str) -> Delivery {
+        Delivery {
+            sink: sink.to_string(),
+            webhook: "http://w".to_string(),
+            sender: "ann".to_string(),
+            text: "a <b> & c".to_string(),
+            forum: false,
+            thread_name: None,
+            embed: None,
+            avatar_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn discord_payloads() {
+        let mut d = delivery("discord");
+        assert_eq!(
+            Discord.payload(&d).await,
+            json!({
+                "username": "ann",
+                "avatar_url": null,
+                "content": "a <b> & c",
+                "thread_name": null,
+            })
+        );
+        d.forum = true;
+        d.thread_name = Some("a thread".to_string());
+        d.avatar_url = Some("http://a".to_string());
+        d.embed = Some(Embed {
+            display_name: "Ann".to_string(),
+            color: Some(0xff0000),
+            badges: vec!["subscriber/12".to_string()],
+            timestamp: "2024-01-01T00:00:00Z".to_string(),
+            channel: "chan".to_string(),
+            tag: "#q".to_string(),
+        });
+        assert_eq!(
+            Discord.payload(&d).await,
+            json!({
+                "username": "ann",
+                "avatar_url": "http://a",
+                "thread_name": "a thread",
+                "embeds": [{
+                    "author": {"name": "Ann"},
+                    "description": "a <b> & c",
+                    "color": 0xff0000,
+                    "timestamp": "2024-01-01T00:00:00Z",
+                    "footer": {"text": "#chan · #q"},
+                    "fields": [{"name": "Badges", "value": "subscriber/12", "inline": true}],
+                }],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn slack_payloads() {
+        let mut d = delivery("slack");
+        d.sender = "<ann>".to_string();
+        assert_eq!(
+            Slack.payload(&d).await,
+            json!({"text": "*&lt;ann&gt;*: a &lt;b&gt; &amp; c"})
+        );
+    }
+
+    #[test]
+    fn accepted() {
+        assert!(Discord.accepted(StatusCode::OK, "").is_ok());
+        assert!(Discord.accepted(StatusCode::NO_CONTENT, "").is_ok());
+        assert_eq!(
+            Discord.accepted(StatusCode::NOT_FOUND, ""),
+            Err("Code: 404 Not Found Reason: Some(\"Not Found\")".to_string())
+        );
+        assert!(Slack.accepted(StatusCode::OK, "ok\n").is_ok());
+        assert_eq!(
+            Slack.accepted(StatusCode::OK, "invalid_payload"),
+            Err("Code: 200 OK Error: invalid_payload".to_string())
+        );
+        assert!(Slack.accepted(StatusCode::NO_CONTENT, "ok").is_err());
+    }
+
+    #[test]
+    fn permanent() {
+        for status in [
+            StatusCode::BAD_REQUEST,
+            StatusCode::FORBIDDEN,
+            StatusCode::NOT_FOUND,
+        ] {
+            assert!(is_permanent(status), "{}", status);
+        }
+        for status in [
+            StatusCode::OK,
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::BAD_GATEWAY,
+        ] {
+            assert!(!is_permanent(status), "{}", status);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+use crate::{metrics, read_config, BotConfig, IRCClient};
+
+struct State {
+    started: Option<SystemTime>,
+    last_message: Option<SystemTime>,
+    last_webhook_error: Option<WebhookError>,
+    // the deactivated channels, shared by the message loop and the webfrontend
+    inactive: BTreeSet<String>,
+    // the cooldowns of these channels are reset with the next message after an activation
+    new_streams: BTreeSet<String>,
+    // the activation survives restarts, not set in the replay mode
+    state_file: Option<String>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    started: None,
+    last_message: None,
+    last_webhook_error: None,
+    inactive: BTreeSet::new(),
+    new_streams: BTreeSet::new(),
+    state_file: None,
+});
+
+// the content of the state file
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct Saved {
+    #[serde(default)]
+    deactivated: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct WebhookError {
+    pub time: String,
+    pub tag: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChannelStatus {
+    pub channel: String,
+    pub joined: bool,
+    pub activated: bool,
+}
+
+/// The answer of `/status`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Status {
+    // "connected" if a channel is joined, otherwise "disconnected"
+    pub connection: String,
+    pub channels: Vec<ChannelStatus>,
+    pub started: Option<String>,
+    pub uptime_seconds: u64,
+    pub last_message: Option<String>,
+    pub last_webhook_error: Option<WebhookError>,
+    pub config_file: String,
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
+
+/// The state is stored next to the config file, e.g. `config.state.json`.
+pub fn state_file(config_file: &str) -> String {
+    Path::new(config_file)
+        .with_extension("state.json")
+        .to_string_lossy()
+        .to_string()
+}
+
+// None without a (readable) state file
+fn saved(state_file: &str) -> Option<Saved> {
+    if !Path::new(state_file).is_file() {
+        return None;
+    }
+    match serde_any::from_file::<Saved, _>(state_file) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            error!("Can't read state file {}: {}", state_file, e);
+            None
+        }
+    }
+}
+
+/// Restores the deactivated channels of the last run.
+pub fn start(config_file: &str) {
+    let state_file = state_file(config_file);
+    let saved = saved(&state_file);
+    let mut state = STATE.lock().unwrap();
+    state.started = Some(SystemTime::now());
+    if let Some(saved) = saved {
+        for channel in saved.deactivated.iter() {
+            info!("still deactivated in #{}", channel);
+        }
+        state.inactive = saved.deactivated;
+    }
+    state.state_file = Some(state_file);
+}
+
+/// Any chat message of a joined channel.
+pub fn message_received() {
+    STATE.lock().unwrap().last_message = Some(SystemTime::now());
+}
+
+pub fn webhook_error(tag: &str, error: &str) {
+    STATE.lock().unwrap().last_webhook_error = Some(WebhookError {
+        time: rfc3339(SystemTime::now()),
+        tag: tag.to_string(),
+        error: error.to_string(),
+    });
+}
+
+/// Activating starts a new stream of the channel (even if the bot is already active).
+pub fn set_active(channel: &str, active: bool) {
+    let channel = channel.to_lowercase();
+    let mut state = STATE.lock().unwrap();
+    if active {
+        state.inactive.remove(&channel);
+        state.new_streams.insert(channel);
+    } else {
+        state.inactive.insert(channel.clone());
+        state.new_streams.remove(&channel);
+    }
+    if let Some(state_file) = &state.state_file {
+        let saved = Saved {
+            deactivated: state.inactive.clone(),
+        };
+        if let Err(e) = serde_any::to_file_pretty(state_file, &saved) {
+            error!("Can't write state file {}: {}", state_file, e);
+        }
+    }
+}
+
+/// Whether the bot was activated in the channel since the last call.
+pub fn take_new_stream(channel: &str) -> bool {
+    STATE
+        .lock()
+        .unwrap()
+        .new_streams
+        .remove(&channel.to_lowercase())
+}
+
+pub fn is_active(channel: &str) -> bool {
+    !STATE
+        .lock()
+        .unwrap()
+        .inactive
+        .contains(&channel.to_lowercase())
+}
+
+pub async fn status(
+    client: &IRCClient,
+    irc_bc: &Arc<RwLock<BotConfig>>,
+    config_file: &str,
+) -> Status {
+    let logins = irc_bc
+        .read()
+        .unwrap()
+        .channels()
+        .map(|c| c.channel.to_lowercase())
+        .collect::<Vec<String>>();
+    let mut channels = vec![];
+    for channel in logins {
+        let (_, joined) = client.get_channel_status(channel.clone()).await;
+        let activated = is_active(&channel);
+        channels.push(ChannelStatus {
+            channel,
+            joined,
+            activated,
+        });
+    }
+    let state = STATE.lock().unwrap();
+    Status {
+        connection: if channels.iter().any(|c| c.joined) {
+            "connected".to_string()
+        } else {
+            "disconnected".to_string()
+        },
+        channels,
+        started: state.started.map(rfc3339),
+        uptime_seconds: state
+            .started
+            .and_then(|s| s.elapsed().ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        last_message: state.last_message.map(rfc3339),
+        last_webhook_error: state.last_webhook_error.clone(),
+        config_file: std::fs::canonicalize(config_file)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| config_file.to_string()),
+    }
+}
+
+/// askbot status <config>
+///
+/// Asks the running bot, fails if it is not reachable or not connected to twitch.
+pub async fn cli(args: &[String]) -> anyhow::Result<()> {
+    let config_file = match args {
+        [config_file] => config_file,
+        _ => anyhow::bail!("usage: askbot status <config>"),
+    };
+    let bc = read_config(config_file)?;
+    if bc.metrics_port == 0 {
+        anyhow::bail!("Set metrics_port in the config file, the status is served there");
+    }
+    // the bot listens on all addresses
+    let host = match metrics::address(&bc).as_str() {
+        "0.0.0.0" | "::" => metrics::METRICS_ADDRESS.to_string(),
+        a if a.contains(':') => format!("[{}]", a),
+        a => a.to_string(),
+    };
+    let status = reqwest::get(format!("http://{}:{}/status", host, bc.metrics_port))
+        .await
+        .map_err(|e| anyhow::anyhow!("The bot is not reachable: {}", e))?
+        .json::<Status>()
+        .await?;
+    println!("{}", serde_json::to_string_pretty(&status)?);
+    if status.connection != "connected" {
+        anyhow::bail!("The bot is not connected to twitch");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn activation_is_saved() {
+        let dir = std::env::temp_dir().join(format!("askbot-status-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let state_file = state_file(&dir.join("config.json").to_string_lossy());
+        assert!(state_file.ends_with("config.state.json"));
+        assert!(saved(&state_file).is_none());
+        let previous = STATE.lock().unwrap().state_file.replace(state_file.clone());
+        let deactivated = || saved(&state_file).unwrap().deactivated;
+
+        set_active("StatusChan", false);
+        assert!(!is_active("statuschan"));
+        assert!(!take_new_stream("statuschan"));
+        // other tests may deactivate their channels at the same time
+        assert!(deactivated().contains("statuschan"));
+
+        set_active("statuschan", true);
+        assert!(is_active("StatusChan"));
+        assert!(!deactivated().contains("statuschan"));
+        assert!(take_new_stream("STATUSCHAN"));
+        assert!(!take_new_stream("statuschan"));
+        // also when it was active already
+        set_active("statuschan", true);
+        assert!(take_new_stream("statuschan"));
+
+        STATE.lock().unwrap().state_file = previous;
+        std::fs::write(&state_file, "{").unwrap();
+        assert!(saved(&state_file).is_none());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,186 @@
+//! Templates with `{placeholder}`s, `{{` and `}}` are literal braces.
+//! The response messages and descriptions predate the templates, other braces are kept there.
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Placeholder(String),
+}
+
+fn parse(template: &str) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) if c.is_ascii_alphanumeric() || c == '_' => name.push(c),
+                        Some(c) => return Err(format!("invalid character '{}' in {{{}", c, name)),
+                        None => return Err(format!("unclosed {{{}", name)),
+                    }
+                }
+                if !text.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut text)));
+                }
+                parts.push(Part::Placeholder(name));
+            }
+            '}' => return Err("unmatched }".to_string()),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+    Ok(parts)
+}
+
+// every brace that isn't part of a {placeholder} is text
+fn parse_text(text: &str) -> Vec<Part> {
+    let mut parts = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        let name_len = rest[start + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .filter(|&i| i > 0 && rest[start + 1 + i..].starts_with('}'));
+        match name_len {
+            Some(len) => {
+                if start > 0 {
+                    parts.push(Part::Text(rest[..start].to_string()));
+                }
+                let name = &rest[start + 1..start + 1 + len];
+                parts.push(Part::Placeholder(name.to_string()));
+                rest = &rest[start + len + 2..];
+            }
+            None => {
+                parts.push(Part::Text(rest[..start + 1].to_string()));
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    parts
+}
+
+/// Checks the syntax and that only the given placeholders are used.
+pub fn check(template: &str, placeholders: &[&str]) -> anyhow::Result<()> {
+    let parts =
+        parse(template).map_err(|e| anyhow::anyhow!("Invalid template \"{}\": {}", template, e))?;
+    for part in parts {
+        if let Part::Placeholder(name) = part {
+            if !placeholders.contains(&name.as_str()) {
+                anyhow::bail!(
+                    "Unknown placeholder {{{}}} in template \"{}\" (known: {})",
+                    name,
+                    template,
+                    placeholders
+                        .iter()
+                        .map(|p| format!("{{{}}}", p))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn fill(parts: Vec<Part>, values: &[(&str, &str)]) -> String {
+    parts
+        .into_iter()
+        .map(|part| match part {
+            Part::Text(text) => text,
+            Part::Placeholder(name) => match values.iter().find(|(n, _)| *n == name) {
+                Some((_, value)) => value.to_string(),
+                None => format!("{{{}}}", name),
+            },
+        })
+        .collect()
+}
+
+/// Fills in the placeholders, unknown ones are kept as they are.
+/// Invalid templates are returned unchanged.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    match parse(template) {
+        Ok(parts) => fill(parts, values),
+        Err(_) => template.to_string(),
+    }
+}
+
+/// Like `render` for response messages and descriptions, braces are no syntax there.
+pub fn render_text(text: &str, values: &[(&str, &str)]) -> String {
+    fill(parse_text(text), values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_placeholders() {
+        assert_eq!(
+            render("{user}: {tags}", &[("user", "bob"), ("tags", "#q")]),
+            "bob: #q"
+        );
+        // unknown placeholders and literal braces
+        assert_eq!(
+            render("{{{user}}} {other}", &[("user", "bob")]),
+            "{bob} {other}"
+        );
+        // invalid templates are kept
+        assert_eq!(render("{user", &[("user", "bob")]), "{user");
+    }
+
+    #[test]
+    fn render_text_keeps_braces() {
+        assert_eq!(
+            render_text("{user} :-{ {} {{user}} }", &[("user", "bob")]),
+            "bob :-{ {} {bob} }"
+        );
+        assert_eq!(render_text("{user", &[("user", "bob")]), "{user");
+        assert_eq!(render_text("{a b} {other}", &[]), "{a b} {other}");
+        assert_eq!(render_text("ä{user}ö", &[("user", "bob")]), "äbobö");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse("a {us er}"),
+            Err("invalid character ' ' in {us".to_string())
+        );
+        assert_eq!(parse("a {user"), Err("unclosed {user".to_string()));
+        assert_eq!(parse("a } b"), Err("unmatched }".to_string()));
+        assert_eq!(
+            parse("a {{b}} {c}"),
+            Ok(vec![
+                Part::Text("a {b} ".to_string()),
+                Part::Placeholder("c".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn check_placeholders() {
+        assert!(check("{user} {tags}", &["user", "tags"]).is_ok());
+        let e = check("{usr}", &["user", "tags"]).unwrap_err().to_string();
+        assert_eq!(
+            e,
+            "Unknown placeholder {usr} in template \"{usr}\" (known: {user}, {tags})"
+        );
+        let e = check("{user", &["user"]).unwrap_err().to_string();
+        assert_eq!(e, "Invalid template \"{user\": unclosed {user");
+    }
+}
@@ -1,3 +1,6 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+
 use std::sync::{Arc, RwLock};
 
 use rocket::http::Status;
@@ -9,12 +12,40 @@ use serde::{Deserialize, Serialize};
 
 pub type Session<'a> = rocket_session::Session<'a, bool>;
 
-use crate::{write_config_logged, BotConfig, Tag};
+use crate::filter::Filter;
+use crate::{announce, write_config_logged, BotConfig, ChannelConfig, Tag};
+use crate::{history, queue};
 
 fn logged_in(session: &Session) -> bool {
     session.tap(|b| *b)
 }
 
+// for the log webhook
+fn webfrontend(remote: Option<std::net::SocketAddr>) -> String {
+    match remote {
+        Some(addr) => format!("the webfrontend ({})", addr.ip()),
+        None => "the webfrontend".to_string(),
+    }
+}
+
+// the optional "channel" query parameter selects the channel, default is the first one.
+fn select_channel<'a>(bc: &'a BotConfig, channel: &Option<String>) -> Option<&'a ChannelConfig> {
+    match channel {
+        Some(c) => bc.channel(c),
+        None => Some(&bc.primary),
+    }
+}
+
+fn select_channel_mut<'a>(
+    bc: &'a mut BotConfig,
+    channel: &Option<String>,
+) -> Option<&'a mut ChannelConfig> {
+    match channel {
+        Some(c) => bc.channel_mut(c),
+        None => Some(&mut bc.primary),
+    }
+}
+
 #[get("/?<_key>")]
 fn index(_session: Session, _key: Option<String>) -> Result<NamedFile, status::Custom<String>> {
     NamedFile::open("index.html").map_err(|e| Custom(Status::NotFound, e.to_string()))
@@ -44,69 +75,144 @@ fn login(
     }
 }
 
-#[post("/add", data = "<tag>", format = "json")]
+#[post("/add?<channel>", data = "<tag>", format = "json")]
 fn add_tag(
     session: Session,
+    channel: Option<String>,
     tag: Json<Tag>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     config_file: rocket::State<String>,
-) -> Status {
+) -> Result<Status, Custom<String>> {
     let mut t = bc.write().unwrap();
     if logged_in(&session) {
-        t.tags.push(tag.into_inner());
+        let mut tag = tag.into_inner();
+        tag.compile()
+            .map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+        match select_channel_mut(&mut t, &channel) {
+            Some(c) => c.tags.push(tag),
+            None => return Ok(Status::NotFound),
+        }
         write_config_logged(&config_file, &t);
-        Status::Created
+        Ok(Status::Created)
     } else {
-        Status::Forbidden
+        Ok(Status::Forbidden)
     }
 }
 
-#[get("/")]
+#[get("/?<channel>")]
 fn get_tags(
     session: Session,
+    channel: Option<String>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
 ) -> Result<Json<Vec<crate::Tag>>, Status> {
     let t = bc.read().unwrap();
     if logged_in(&session) {
-        Ok(Json(t.tags.clone()))
+        select_channel(&t, &channel)
+            .map(|c| Json(c.tags.clone()))
+            .ok_or(Status::NotFound)
     } else {
         Err(Status::Forbidden)
     }
 }
 
-#[delete("/<id>")]
+#[delete("/<id>?<channel>")]
 fn delete_tag(
     session: Session,
     id: usize,
+    channel: Option<String>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     config_file: rocket::State<String>,
 ) -> Status {
     let mut t = bc.write().unwrap();
-    if logged_in(&session) && id < t.tags.len() {
-        let _ = t.tags.remove(id);
-        write_config_logged(&config_file, &t);
-        Status::Ok
-    } else {
-        Status::Forbidden
+    if !logged_in(&session) {
+        return Status::Forbidden;
+    }
+    match select_channel_mut(&mut t, &channel) {
+        Some(c) if id < c.tags.len() => {
+            let _ = c.tags.remove(id);
+        }
+        Some(_) => return Status::Forbidden,
+        None => return Status::NotFound,
+    }
+    write_config_logged(&config_file, &t);
+    Status::Ok
+}
+
+// the fields of the request replace the fields of the tag, the others are kept
+fn merge_tag(tag: &Tag, fields: serde_json::Value) -> anyhow::Result<Tag> {
+    let mut value = serde_json::to_value(tag)?;
+    match (value.as_object_mut(), fields) {
+        (Some(old), serde_json::Value::Object(new)) => old.extend(new),
+        _ => anyhow::bail!("The tag must be an object"),
     }
+    let mut tag: Tag = serde_json::from_value(value)?;
+    tag.compile()?;
+    Ok(tag)
 }
 
-#[put("/<id>", data = "<tag>", format = "json")]
+#[put("/<id>?<channel>", data = "<fields>", format = "json")]
 fn update_tag(
     session: Session,
     id: usize,
-    tag: Json<Tag>,
+    channel: Option<String>,
+    fields: Json<serde_json::Value>,
+    bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
+    config_file: rocket::State<String>,
+) -> Result<Status, Custom<String>> {
+    let mut t = bc.write().unwrap();
+    if !logged_in(&session) {
+        return Ok(Status::Forbidden);
+    }
+    match select_channel_mut(&mut t, &channel) {
+        Some(c) if id < c.tags.len() => {
+            c.tags[id] = merge_tag(&c.tags[id], fields.into_inner())
+                .map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+        }
+        Some(_) => return Ok(Status::Forbidden),
+        None => return Ok(Status::NotFound),
+    }
+    write_config_logged(&config_file, &t);
+    Ok(Status::Ok)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct Enabled {
+    enabled: bool,
+}
+
+// like #enable/#disable in a whisper, keeps the webhook of the tag
+#[post("/<id>/enabled?<channel>", data = "<enabled>", format = "json")]
+fn enable_tag(
+    session: Session,
+    id: usize,
+    channel: Option<String>,
+    enabled: Json<Enabled>,
+    remote: Option<std::net::SocketAddr>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     config_file: rocket::State<String>,
 ) -> Status {
     let mut t = bc.write().unwrap();
-    if logged_in(&session) && id < t.tags.len() {
-        t.tags[id] = tag.into_inner();
-        write_config_logged(&config_file, &t);
-        Status::Ok
-    } else {
-        Status::Forbidden
+    if !logged_in(&session) {
+        return Status::Forbidden;
     }
+    let (channel, tag) = match select_channel_mut(&mut t, &channel) {
+        Some(c) if id < c.tags.len() => {
+            c.tags[id].enabled = enabled.enabled;
+            (c.channel.clone(), c.tags[id].tag.clone())
+        }
+        Some(_) => return Status::Forbidden,
+        None => return Status::NotFound,
+    };
+    write_config_logged(&config_file, &t);
+    let who = webfrontend(remote);
+    let message = if enabled.enabled {
+        format!("{} (#{}): Tag enabled: {}", who, channel, tag)
+    } else {
+        format!("{} (#{}): Tag disabled: {}", who, channel, tag)
+    };
+    info!("{}", message);
+    announce(message);
+    Status::Ok
 }
 
 // frontend settings json
@@ -119,6 +225,12 @@ struct Settings {
     message_success: String,
     message_failure: String,
     reply: bool,
+    // read only, the activation of the channel, see /activation
+    #[serde(skip_deserializing)]
+    activated: bool,
+    // read only, all channels for the "channel" parameter, the first one is `channel`
+    #[serde(skip_deserializing)]
+    channels: Vec<String>,
 }
 
 #[get("/")]
@@ -130,12 +242,14 @@ fn get_settings(
     let t = bc.read().unwrap();
     if logged_in(&session) {
         let settings = Settings {
-            channel: t.channel.clone(),
+            channel: t.primary.channel.clone(),
             username: t.username.clone(),
             oauth: t.oauth_token.clone(),
-            message_success: t.response_message_success.clone(),
-            message_failure: t.response_message_failure.clone(),
-            reply: t.use_reply,
+            message_success: t.primary.response_message_success.clone(),
+            message_failure: t.primary.response_message_failure.clone(),
+            reply: t.primary.use_reply,
+            activated: crate::status::is_active(&t.primary.channel),
+            channels: t.channels().map(|c| c.channel.clone()).collect(),
         };
         Ok(Json(settings))
     } else {
@@ -153,12 +267,12 @@ fn update_settings(
 ) -> Status {
     let mut t = bc.write().unwrap();
     if logged_in(&session) {
-        t.channel = settings.channel.clone();
+        t.primary.channel = settings.channel.clone();
         t.username = settings.username.clone();
         t.oauth_token = settings.oauth.clone();
-        t.response_message_success = settings.message_success.clone();
-        t.response_message_failure = settings.message_failure.clone();
-        t.use_reply = settings.reply;
+        t.primary.response_message_success = settings.message_success.clone();
+        t.primary.response_message_failure = settings.message_failure.clone();
+        t.primary.use_reply = settings.reply;
         write_config_logged(&config_file, &t);
         Status::Ok
     } else {
@@ -172,15 +286,17 @@ struct Message {
     value: String,
 }
 
-#[get("/<name>")]
+#[get("/<name>?<channel>")]
 fn get_message(
     session: Session,
     name: String,
+    channel: Option<String>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     _config_file: rocket::State<String>,
 ) -> Result<Json<Message>, Status> {
     if logged_in(&session) {
-        let t = bc.read().unwrap();
+        let bc = bc.read().unwrap();
+        let t = select_channel(&bc, &channel).ok_or(Status::NotFound)?;
         match name.as_str() {
             "response_message_success" => Ok(Json(Message {
                 name,
@@ -197,43 +313,44 @@ fn get_message(
     }
 }
 
-#[post("/<name>", data = "<msg>", format = "json")]
+#[post("/<name>?<channel>", data = "<msg>", format = "json")]
 fn set_message(
     session: Session,
     name: String,
+    channel: Option<String>,
     msg: Json<String>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     config_file: rocket::State<String>,
 ) -> Status {
     if logged_in(&session) {
-        let mut t = bc.write().unwrap();
+        let mut bc = bc.write().unwrap();
         let msg = msg.into_inner();
+        let t = match select_channel_mut(&mut bc, &channel) {
+            Some(t) => t,
+            None => return Status::NotFound,
+        };
         match name.as_str() {
-            "response_message_success" => {
-                t.response_message_success = msg;
-                write_config_logged(&config_file, &t);
-                Status::Created
-            }
-            "response_message_failure" => {
-                t.response_message_failure = msg;
-                write_config_logged(&config_file, &t);
-                Status::Created
-            }
-            _ => Status::NotFound,
+            "response_message_success" => t.response_message_success = msg,
+            "response_message_failure" => t.response_message_failure = msg,
+            _ => return Status::NotFound,
         }
+        write_config_logged(&config_file, &bc);
+        Status::Created
     } else {
         return Status::Forbidden;
     }
 }
 
-#[get("/")]
+#[get("/?<channel>")]
 fn get_messages(
     session: Session,
+    channel: Option<String>,
     bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
     _config_file: rocket::State<String>,
 ) -> Result<Json<Vec<Message>>, Status> {
     if logged_in(&session) {
-        let t = bc.read().unwrap();
+        let bc = bc.read().unwrap();
+        let t = select_channel(&bc, &channel).ok_or(Status::NotFound)?;
         Ok(Json(vec![
             Message {
                 name: "response_message_success".to_string(),
@@ -249,14 +366,252 @@ fn get_messages(
     }
 }
 
+#[get("/")]
+fn get_queue(
+    session: Session,
+    config_file: rocket::State<String>,
+) -> Result<Json<Vec<queue::Entry>>, Custom<String>> {
+    if logged_in(&session) {
+        queue::entries(&queue::queue_file(&config_file))
+            .map(Json)
+            .map_err(internal_error)
+    } else {
+        Err(Custom(Status::Forbidden, "".to_string()))
+    }
+}
+
+#[post("/<id>/retry")]
+fn retry_queued(
+    session: Session,
+    id: u64,
+    config_file: rocket::State<String>,
+) -> Result<Status, Custom<String>> {
+    if !logged_in(&session) {
+        Ok(Status::Forbidden)
+    } else if queue::reschedule(&queue::queue_file(&config_file), id).map_err(internal_error)? {
+        Ok(Status::Ok)
+    } else {
+        Ok(Status::NotFound)
+    }
+}
+
+#[delete("/<id>")]
+fn drop_queued(
+    session: Session,
+    id: u64,
+    config_file: rocket::State<String>,
+) -> Result<Status, Custom<String>> {
+    if !logged_in(&session) {
+        Ok(Status::Forbidden)
+    } else if queue::drop_entry(&queue::queue_file(&config_file), id).map_err(internal_error)? {
+        Ok(Status::Ok)
+    } else {
+        Ok(Status::NotFound)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct Activation {
+    activated: bool,
+}
+
+#[get("/?<channel>")]
+fn get_activation(
+    session: Session,
+    channel: Option<String>,
+    bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
+) -> Result<Json<Activation>, Status> {
+    if logged_in(&session) {
+        let bc = bc.read().unwrap();
+        let c = select_channel(&bc, &channel).ok_or(Status::NotFound)?;
+        Ok(Json(Activation {
+            activated: crate::status::is_active(&c.channel),
+        }))
+    } else {
+        Err(Status::Forbidden)
+    }
+}
+
+// like #activate/#deactivate in the chat of the channel
+#[post("/?<channel>", data = "<activation>", format = "json")]
+fn set_activation(
+    session: Session,
+    channel: Option<String>,
+    activation: Json<Activation>,
+    remote: Option<std::net::SocketAddr>,
+    bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
+) -> Status {
+    if !logged_in(&session) {
+        return Status::Forbidden;
+    }
+    let channel = match select_channel(&bc.read().unwrap(), &channel) {
+        Some(c) => c.channel.to_lowercase(),
+        None => return Status::NotFound,
+    };
+    let activated = activation.activated;
+    crate::status::set_active(&channel, activated);
+    let who = webfrontend(remote);
+    let message = if activated {
+        format!("activated by {} (#{})", who, channel)
+    } else {
+        format!("deactivated by {} (#{})", who, channel)
+    };
+    info!("{}", message);
+    announce(message);
+    Status::Ok
+}
+
+// entries per page of the history
+const HISTORY_PER_PAGE: usize = 50;
+const HISTORY_MAX_PER_PAGE: usize = 500;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct HistoryPage {
+    // the first page is 0
+    page: usize,
+    per_page: usize,
+    // entries matching the filters on all pages
+    total: usize,
+    entries: Vec<history::Entry>,
+}
+
+fn internal_error(e: anyhow::Error) -> Custom<String> {
+    Custom(Status::InternalServerError, e.to_string())
+}
+
+// dates are "YYYY-MM-DD" or rfc 3339, like the history cli
+#[allow(clippy::too_many_arguments)]
+#[get("/?<page>&<per_page>&<channel>&<tag>&<user>&<status>&<search>&<since>&<until>")]
+fn get_history(
+    session: Session,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    channel: Option<String>,
+    tag: Option<String>,
+    user: Option<String>,
+    status: Option<String>,
+    search: Option<String>,
+    since: Option<String>,
+    until: Option<String>,
+    config_file: rocket::State<String>,
+) -> Result<Json<HistoryPage>, Custom<String>> {
+    if !logged_in(&session) {
+        return Err(Custom(Status::Forbidden, "".to_string()));
+    }
+    let time = |t: Option<String>, end: bool| {
+        t.map(|t| history::parse_time(&t, end))
+            .transpose()
+            .map_err(|e| Custom(Status::BadRequest, e.to_string()))
+    };
+    let page = page.unwrap_or(0);
+    let per_page = per_page
+        .unwrap_or(HISTORY_PER_PAGE)
+        .clamp(1, HISTORY_MAX_PER_PAGE);
+    let offset = page
+        .checked_mul(per_page)
+        .ok_or_else(|| Custom(Status::BadRequest, format!("Invalid page {}", page)))?;
+    let query = history::Query {
+        since: time(since, false)?,
+        until: time(until, true)?,
+        channel,
+        tag,
+        user,
+        status,
+        search,
+        limit: per_page,
+        offset,
+    };
+    let history_file = history::history_file(&config_file);
+    Ok(Json(HistoryPage {
+        page,
+        per_page,
+        total: history::count(&history_file, &query).map_err(internal_error)?,
+        entries: history::query(&history_file, &query).map_err(internal_error)?,
+    }))
+}
+
+// failed entries are queued again, the retry task updates the status on success
+#[post("/<id>/resend")]
+fn resend_history(
+    session: Session,
+    id: i64,
+    config_file: rocket::State<String>,
+) -> Result<Status, Custom<String>> {
+    if !logged_in(&session) {
+        return Ok(Status::Forbidden);
+    }
+    let entry = match history::get(&history::history_file(&config_file), id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return Ok(Status::NotFound),
+        Err(e) => return Err(internal_error(e)),
+    };
+    match entry.delivery {
+        Some(delivery) if entry.status == history::FAILED => {
+            queue::resend(
+                &queue::queue_file(&config_file),
+                entry.channel,
+                entry.tag,
+                delivery,
+                id,
+            )
+            .map_err(internal_error)?;
+            Ok(Status::Accepted)
+        }
+        _ => Err(Custom(
+            Status::BadRequest,
+            format!("Only failed messages can be resent ({})", entry.status),
+        )),
+    }
+}
+
+#[get("/")]
+fn get_filter(
+    session: Session,
+    bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
+) -> Result<Json<Filter>, Status> {
+    if logged_in(&session) {
+        Ok(Json(bc.read().unwrap().filter.clone()))
+    } else {
+        Err(Status::Forbidden)
+    }
+}
+
+// the filter of all channels, the filters of the tags are part of the tags
+#[post("/", data = "<filter>", format = "json")]
+fn set_filter(
+    session: Session,
+    filter: Json<Filter>,
+    bc: rocket::State<'_, Arc<RwLock<BotConfig>>>,
+    config_file: rocket::State<String>,
+) -> Result<Status, Custom<String>> {
+    let mut bc = bc.write().unwrap();
+    if !logged_in(&session) {
+        return Ok(Status::Forbidden);
+    }
+    let mut filter = filter.into_inner();
+    filter
+        .compile()
+        .map_err(|e| Custom(Status::BadRequest, e.to_string()))?;
+    bc.filter = filter;
+    write_config_logged(&config_file, &bc);
+    Ok(Status::Ok)
+}
+
 pub fn rocket(bc: Arc<RwLock<BotConfig>>, config_file: String) -> rocket::Rocket {
     rocket::ignite()
         .manage(bc)
         .manage(config_file)
         .mount("/", routes![index, login])
-        .mount("/tags", routes![add_tag, delete_tag, get_tags, update_tag])
+        .mount(
+            "/tags",
+            routes![add_tag, delete_tag, get_tags, update_tag, enable_tag],
+        )
         .mount("/settings", routes![update_settings, get_settings])
         .mount("/messages", routes![get_message, get_messages, set_message])
+        .mount("/queue", routes![get_queue, retry_queued, drop_queued])
+        .mount("/filter", routes![get_filter, set_filter])
+        .mount("/history", routes![get_history, resend_history])
+        .mount("/activation", routes![get_activation, set_activation])
         .attach(Session::fairing())
 }
 
@@ -345,8 +700,47 @@ mod test {
                 .drain(..)
                 .filter(|c| *c != '\n' && *c != ' ')
                 .collect::<String>()),
-            serde_json::to_string(&bc.read().unwrap().tags).ok()
+            serde_json::to_string(&bc.read().unwrap().primary.tags).ok()
+        );
+    }
+
+    #[test]
+    fn get_tags_of_channel() {
+        let (mut client, bc) = prepare_client_bc();
+
+        do_login(&mut client);
+
+        let channel = bc.read().unwrap().primary.channel.clone();
+        let channel = rocket::http::uri::Uri::percent_encode(&channel);
+        let mut response = client.get(format!("/tags/?channel={}", channel)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.body_string().map(|mut s| s
+                .drain(..)
+                .filter(|c| *c != '\n' && *c != ' ')
+                .collect::<String>()),
+            serde_json::to_string(&bc.read().unwrap().primary.tags).ok()
         );
+
+        // unknown channel
+        let response = client.get("/tags/?channel=unknown").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn settings_list_channels() {
+        let (mut client, bc) = prepare_client_bc();
+
+        do_login(&mut client);
+
+        let mut response = client.get("/settings/").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let settings: serde_json::Value =
+            serde_json::from_str(&response.body_string().unwrap()).unwrap();
+        let bc = bc.read().unwrap();
+        let channels = bc.channels().map(|c| c.channel.clone()).collect::<Vec<_>>();
+        assert_eq!(settings["channels"], serde_json::json!(channels));
+        assert_eq!(settings["channel"], serde_json::json!(bc.primary.channel));
     }
 
     #[test]
@@ -355,7 +749,7 @@ mod test {
 
         do_login(&mut client);
 
-        let old_count = bc.read().unwrap().tags.len();
+        let old_count = bc.read().unwrap().primary.tags.len();
         let mut response = client
             .post("/tags/add")
             .header(rocket::http::ContentType::JSON)
@@ -369,17 +763,180 @@ mod test {
             .dispatch();
         assert_eq!(response.status(), Status::Created);
         assert_eq!(response.body_string(), None);
-        let new_count = bc.read().unwrap().tags.len();
+        let new_count = bc.read().unwrap().primary.tags.len();
         assert_eq!(new_count, old_count + 1);
     }
 
+    #[test]
+    fn queue() {
+        let (mut client, _) = prepare_client_bc();
+
+        // not logged in
+        assert_eq!(client.get("/queue/").dispatch().status(), Status::Forbidden);
+
+        do_login(&mut client);
+
+        let response = client.get("/queue/").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // unknown entry
+        let response = client.delete(format!("/queue/{}", u64::MAX)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.post(format!("/queue/{}/retry", u64::MAX)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn history() {
+        let (mut client, _) = prepare_client_bc();
+
+        // not logged in
+        assert_eq!(
+            client.get("/history/").dispatch().status(),
+            Status::Forbidden
+        );
+
+        do_login(&mut client);
+
+        let response = client.get("/history/?page=0&tag=%23firsttag").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        // invalid date
+        let response = client.get("/history/?since=yesterday").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // the offset of the page overflows
+        let response = client
+            .get(format!("/history/?page={}", usize::MAX))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // unknown entry
+        let response = client
+            .post(format!("/history/{}/resend", i64::MAX))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn activation() {
+        let (mut client, bc) = prepare_client_bc();
+
+        // not logged in
+        assert_eq!(
+            client.get("/activation/").dispatch().status(),
+            Status::Forbidden
+        );
+
+        do_login(&mut client);
+
+        let response = client
+            .post("/activation/")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"activated\": false}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let mut response = client.get("/activation/").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.body_string(),
+            Some("{\"activated\":false}".to_string())
+        );
+
+        let response = client
+            .post("/activation/")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"activated\": true}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(crate::status::is_active(
+            &bc.read().unwrap().primary.channel
+        ));
+
+        // unknown channel
+        let response = client
+            .post("/activation/?channel=unknown")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"activated\": false}")
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert!(crate::status::is_active("unknown"));
+    }
+
+    #[test]
+    fn add_invalid_tag() {
+        let (mut client, bc) = prepare_client_bc();
+
+        do_login(&mut client);
+
+        let old_count = bc.read().unwrap().primary.tags.len();
+        let mut response = client
+            .post("/tags/add")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                rocket_contrib::json!({
+                    "tag": "#(q|question",
+                    "webhook": "test-hook",
+                    "match_mode": "regex"
+                })
+                .to_string(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().unwrap().contains("#(q|question"));
+        let new_count = bc.read().unwrap().primary.tags.len();
+        assert_eq!(new_count, old_count);
+    }
+
+    #[test]
+    fn set_message_with_braces() {
+        let (mut client, bc) = prepare_client_bc();
+
+        do_login(&mut client);
+
+        // braces are text in the response messages
+        let response = client
+            .post("/messages/response_message_success")
+            .header(rocket::http::ContentType::JSON)
+            .body(rocket_contrib::json!("Thanks {user :-}").to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let new_message = bc.read().unwrap().primary.response_message_success.clone();
+        assert_eq!(new_message, "Thanks {user :-}");
+    }
+
+    #[test]
+    fn set_invalid_filter() {
+        let (mut client, bc) = prepare_client_bc();
+
+        assert_eq!(client.get("/filter").dispatch().status(), Status::Forbidden);
+
+        do_login(&mut client);
+
+        let old_filter = bc.read().unwrap().filter.clone();
+        let mut response = client
+            .post("/filter")
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                rocket_contrib::json!({
+                    "blocked_patterns": ["free (nitro"],
+                    "links": "allowlist"
+                })
+                .to_string(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.body_string().unwrap().contains("free (nitro"));
+        assert_eq!(bc.read().unwrap().filter, old_filter);
+    }
+
     #[test]
     fn delete_tag() {
         let (mut client, bc) = prepare_client_bc();
         do_login(&mut client);
 
         // get old data
-        let old_count = bc.read().unwrap().tags.len();
+        let old_count = bc.read().unwrap().primary.tags.len();
         assert!(old_count > 0);
 
         // delete
@@ -387,7 +944,7 @@ mod test {
         assert_eq!(response.status(), Status::Ok);
 
         // check
-        let new_count = bc.read().unwrap().tags.len();
+        let new_count = bc.read().unwrap().primary.tags.len();
         assert_eq!(new_count, old_count - 1);
     }
 
@@ -397,9 +954,9 @@ mod test {
         do_login(&mut client);
 
         // get old data
-        let old_count = bc.read().unwrap().tags.len();
+        let old_count = bc.read().unwrap().primary.tags.len();
         assert!(old_count > 0);
-        let mut old_tag = bc.read().unwrap().tags[0].clone();
+        let mut old_tag = bc.read().unwrap().primary.tags[0].clone();
 
         let mut new_tag = old_tag.clone();
         let number = old_tag.tag.split_off(5).parse::<i32>().unwrap();
@@ -420,9 +977,91 @@ mod test {
         assert_eq!(response.status(), Status::Ok);
 
         // check
-        let new_count = bc.read().unwrap().tags.len();
+        let new_count = bc.read().unwrap().primary.tags.len();
         assert_eq!(new_count, old_count);
-        let updated_tag = bc.read().unwrap().tags[0].clone();
+        let updated_tag = bc.read().unwrap().primary.tags[0].clone();
         assert_eq!(new_tag, updated_tag);
     }
+
+    #[test]
+    fn update_tag_keeps_fields() {
+        let (mut client, bc) = prepare_client_bc();
+        do_login(&mut client);
+
+        bc.write().unwrap().primary.tags[1].strip_tag = true;
+        let old_tag = bc.read().unwrap().primary.tags[1].clone();
+
+        // the webfrontend only sends the tag and the webhook
+        let response = client
+            .put(format!("/tags/{}", 1))
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                rocket_contrib::json!({
+                    "tag": old_tag.tag,
+                    "webhook": "changed-hook"
+                })
+                .to_string(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let updated_tag = bc.read().unwrap().primary.tags[1].clone();
+        assert_eq!(updated_tag.webhook, "changed-hook");
+        assert!(updated_tag.strip_tag);
+
+        // invalid field
+        let response = client
+            .put(format!("/tags/{}", 1))
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"match_mode\": \"unknown\"}")
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // restore the test config
+        let response = client
+            .put(format!("/tags/{}", 1))
+            .header(rocket::http::ContentType::JSON)
+            .body(
+                rocket_contrib::json!({
+                    "webhook": old_tag.webhook,
+                    "strip_tag": false
+                })
+                .to_string(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn enable_tag() {
+        let (mut client, bc) = prepare_client_bc();
+        do_login(&mut client);
+
+        let old_tag = bc.read().unwrap().primary.tags[0].clone();
+
+        let response = client
+            .post("/tags/0/enabled")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"enabled\": false}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let disabled_tag = bc.read().unwrap().primary.tags[0].clone();
+        assert!(!disabled_tag.enabled);
+        assert_eq!(disabled_tag.webhook, old_tag.webhook);
+
+        let response = client
+            .post("/tags/0/enabled")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"enabled\": true}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(bc.read().unwrap().primary.tags[0].enabled);
+
+        // unknown tag
+        let response = client
+            .post("/tags/1000/enabled")
+            .header(rocket::http::ContentType::JSON)
+            .body("{\"enabled\": false}")
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
 }
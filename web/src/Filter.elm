@@ -0,0 +1,58 @@
+module Filter exposing (..)
+
+import Json.Decode as Decode exposing (Decoder, int, list, string)
+import Json.Decode.Pipeline exposing (optional)
+import Json.Encode as Encode
+
+
+type alias Filter =
+    { blockedWords : List String
+    , blockedPatterns : List String
+    , links : String
+    , allowedDomains : List String
+    , minLength : Int
+    , maxLength : Int
+    }
+
+
+linkPolicies : List String
+linkPolicies =
+    [ "allow", "deny", "allowlist" ]
+
+
+new : Filter
+new =
+    { blockedWords = []
+    , blockedPatterns = []
+    , links = "allow"
+    , allowedDomains = []
+    , minLength = 0
+    , maxLength = 0
+    }
+
+
+decode : Decoder Filter
+decode =
+    Decode.succeed Filter
+        |> Json.Decode.Pipeline.optional "blocked_words" (Decode.list Decode.string) []
+        |> Json.Decode.Pipeline.optional "blocked_patterns" (Decode.list Decode.string) []
+        |> Json.Decode.Pipeline.optional "links" Decode.string "allow"
+        |> Json.Decode.Pipeline.optional "allowed_domains" (Decode.list Decode.string) []
+        |> Json.Decode.Pipeline.optional "min_length" Decode.int 0
+        |> Json.Decode.Pipeline.optional "max_length" Decode.int 0
+
+
+nonBlank : List String -> List String
+nonBlank =
+    List.filter (\s -> String.trim s /= "")
+
+
+toJson filter =
+    Encode.object
+        [ ( "blocked_words", Encode.list Encode.string <| nonBlank filter.blockedWords )
+        , ( "blocked_patterns", Encode.list Encode.string <| nonBlank filter.blockedPatterns )
+        , ( "links", Encode.string filter.links )
+        , ( "allowed_domains", Encode.list Encode.string <| nonBlank filter.allowedDomains )
+        , ( "min_length", Encode.int filter.minLength )
+        , ( "max_length", Encode.int filter.maxLength )
+        ]
@@ -0,0 +1,162 @@
+module History exposing (..)
+
+import Json.Decode as Decode exposing (Decoder, int, list, string)
+import Json.Decode.Pipeline exposing (required)
+import Time
+import Url.Builder
+
+
+type alias Entry =
+    { id : Int
+    , channel : String
+    , login : String
+    , text : String
+    , timestamp : Int
+    , tag : String
+    , status : String
+    }
+
+
+type alias Page =
+    { page : Int
+    , perPage : Int
+    , total : Int
+    , entries : List Entry
+    }
+
+
+type alias Query =
+    { page : Int
+    , search : String
+    , tag : String
+    , user : String
+    , status : String
+    , since : String
+    , until : String
+    }
+
+
+statuses : List String
+statuses =
+    [ "delivered", "failed", "duplicate", "cooldown", "filtered", "denied", "empty" ]
+
+
+emptyPage : Page
+emptyPage =
+    { page = 0, perPage = 50, total = 0, entries = [] }
+
+
+newQuery : Query
+newQuery =
+    { page = 0, search = "", tag = "", user = "", status = "", since = "", until = "" }
+
+
+decodeEntry : Decoder Entry
+decodeEntry =
+    Decode.succeed Entry
+        |> Json.Decode.Pipeline.required "id" Decode.int
+        |> Json.Decode.Pipeline.required "channel" Decode.string
+        |> Json.Decode.Pipeline.required "login" Decode.string
+        |> Json.Decode.Pipeline.required "text" Decode.string
+        |> Json.Decode.Pipeline.required "timestamp" Decode.int
+        |> Json.Decode.Pipeline.required "tag" Decode.string
+        |> Json.Decode.Pipeline.required "status" Decode.string
+
+
+decode : Decoder Page
+decode =
+    Decode.succeed Page
+        |> Json.Decode.Pipeline.required "page" Decode.int
+        |> Json.Decode.Pipeline.required "per_page" Decode.int
+        |> Json.Decode.Pipeline.required "total" Decode.int
+        |> Json.Decode.Pipeline.required "entries" (Decode.list decodeEntry)
+
+
+path : String -> Query -> String
+path channel q =
+    "history/"
+        ++ Url.Builder.toQuery
+            (Url.Builder.int "page" q.page
+                :: List.filterMap
+                    (\( name, value ) ->
+                        if String.isEmpty value then
+                            Nothing
+
+                        else
+                            Just <| Url.Builder.string name value
+                    )
+                    [ ( "channel", channel )
+                    , ( "search", q.search )
+                    , ( "tag", q.tag )
+                    , ( "user", q.user )
+                    , ( "status", q.status )
+                    , ( "since", q.since )
+                    , ( "until", q.until )
+                    ]
+            )
+
+
+lastPage : Page -> Int
+lastPage p =
+    max 0 <| (p.total - 1) // p.perPage
+
+
+monthNumber : Time.Month -> Int
+monthNumber month =
+    case month of
+        Time.Jan ->
+            1
+
+        Time.Feb ->
+            2
+
+        Time.Mar ->
+            3
+
+        Time.Apr ->
+            4
+
+        Time.May ->
+            5
+
+        Time.Jun ->
+            6
+
+        Time.Jul ->
+            7
+
+        Time.Aug ->
+            8
+
+        Time.Sep ->
+            9
+
+        Time.Oct ->
+            10
+
+        Time.Nov ->
+            11
+
+        Time.Dec ->
+            12
+
+
+formatTime : Int -> String
+formatTime timestamp =
+    let
+        time =
+            Time.millisToPosix <| timestamp * 1000
+
+        pad n =
+            String.padLeft 2 '0' <| String.fromInt n
+    in
+    String.fromInt (Time.toYear Time.utc time)
+        ++ "-"
+        ++ pad (monthNumber <| Time.toMonth Time.utc time)
+        ++ "-"
+        ++ pad (Time.toDay Time.utc time)
+        ++ " "
+        ++ pad (Time.toHour Time.utc time)
+        ++ ":"
+        ++ pad (Time.toMinute Time.utc time)
+        ++ " UTC"
@@ -5,19 +5,25 @@ import Bootstrap.Alert as Alert
 import Bootstrap.Button as Button
 import Bootstrap.ButtonGroup as BG
 import Bootstrap.CDN as CDN
+import Bootstrap.Form as Form
 import Bootstrap.Form.Checkbox as Checkbox
 import Bootstrap.Form.Fieldset as Fieldset
 import Bootstrap.Form.Input as Input
 import Bootstrap.Form.InputGroup as InputGroup
+import Bootstrap.Form.Select as Select
+import Bootstrap.Form.Textarea as Textarea
 import Bootstrap.Tab as Tab
 import Bootstrap.Table as Table
 import Browser
 import Browser.Navigation as Nav
 import Error
+import Filter exposing (Filter)
+import History
 import Html exposing (..)
 import Html.Attributes exposing (..)
 import Html.Events exposing (onClick, onInput)
 import Http exposing (Error(..))
+import Json.Decode as Decode
 import Json.Encode as Encode
 import List.Extra exposing (removeAt, setAt, updateAt, updateIf)
 import Message exposing (Message)
@@ -25,6 +31,7 @@ import Requests
 import Settings exposing (Settings)
 import Tag exposing (Tag, TagAction(..))
 import Url
+import Url.Builder
 
 
 main : Program () Model Msg
@@ -51,6 +58,13 @@ type alias Model =
     , alerts : Alert.Alerts Msg
     , settings : Settings
     , credentialsChanged : Bool
+    , filter : Filter
+    , history : History.Page
+    , historyQuery : History.Query
+    , activated : Bool
+
+    -- the channel of the tags, messages, activation and history, empty until the settings are loaded
+    , channel : String
     }
 
 
@@ -58,6 +72,15 @@ loginJson key =
     Encode.object [ ( "key", Encode.string key ) ]
 
 
+activationJson activated =
+    Encode.object [ ( "activated", Encode.bool activated ) ]
+
+
+decodeActivation : Decode.Decoder Bool
+decodeActivation =
+    Decode.field "activated" Decode.bool
+
+
 extractKey : String -> String
 extractKey queryString =
     let
@@ -101,8 +124,14 @@ init _ url _ =
         , messageSuccess = ""
         , messageFailure = ""
         , reply = True
+        , channels = []
         }
         False
+        Filter.new
+        History.emptyPage
+        History.newQuery
+        True
+        ""
     , Requests.post { base_url = base_url } Login "login" <| loginJson loginKey
     )
 
@@ -118,6 +147,7 @@ type Msg
     | SendLogin String
     | RemoveTag Int
     | UpdateTag Int Tag
+    | EnableTag Int Bool
     | UpdateSettings Settings
     | UpdateMessageText String String
     | UpdateMessage String String
@@ -132,6 +162,38 @@ type Msg
     | RemoveAlert Int Alert.Visibility
     | UpdateSettingsText String String
     | UpdateSettingsReply Bool
+    | ActivationLoaded (Result Http.Error Bool)
+    | SetActivation Bool
+    | ActivationUpdated Bool (Result Http.Error ())
+    | FilterLoaded (Result Http.Error Filter)
+    | UpdateFilter Filter
+    | SaveFilter Filter
+    | FilterSaved (Result Http.Error ())
+    | HistoryLoaded (Result Http.Error History.Page)
+    | UpdateHistoryQuery History.Query
+    | LoadHistory History.Query
+    | Resend Int
+    | Resent (Result Http.Error ())
+    | SelectChannel String
+
+
+channelPath : Model -> String -> String
+channelPath model path =
+    if String.isEmpty model.channel then
+        path
+
+    else
+        path ++ Url.Builder.toQuery [ Url.Builder.string "channel" model.channel ]
+
+
+loadChannel : Model -> Cmd Msg
+loadChannel model =
+    Cmd.batch
+        [ Requests.get model Tags Tag.decodeList <| channelPath model "tags/"
+        , Requests.get model Messages Message.decodeList <| channelPath model "messages/"
+        , Requests.get model ActivationLoaded decodeActivation <| channelPath model "activation/"
+        , Requests.get model HistoryLoaded History.decode <| History.path model.channel model.historyQuery
+        ]
 
 
 update : Msg -> Model -> ( Model, Cmd Msg )
@@ -140,9 +202,9 @@ update msg model =
         Login (Ok _) ->
             ( { model | login = True, alerts = Alert.new model.alerts.alertAction }
             , Cmd.batch
-                [ Requests.get model Tags Tag.decodeList "tags/"
-                , Requests.get model Messages Message.decodeList "messages/"
+                [ loadChannel model
                 , Requests.get model Settings Settings.decode "settings/"
+                , Requests.get model FilterLoaded Filter.decode "filter/"
                 ]
             )
 
@@ -202,9 +264,16 @@ update msg model =
             )
 
         Settings (Ok s) ->
-            ( { model | settings = s }
-            , Cmd.none
-            )
+            if String.isEmpty model.channel then
+                -- the history of all channels was loaded until now
+                ( { model | settings = s, channel = s.channel }
+                , Requests.get model HistoryLoaded History.decode <| History.path s.channel model.historyQuery
+                )
+
+            else
+                ( { model | settings = s }
+                , Cmd.none
+                )
 
         Settings (Err e) ->
             ( Alert.add model Alert.dismissableAlert <|
@@ -269,21 +338,30 @@ update msg model =
 
         UpdateMessage name message ->
             ( model
-            , Requests.post model UpdatedMessage ("messages/" ++ name) <| Encode.string message
+            , Requests.post model UpdatedMessage (channelPath model <| "messages/" ++ name) <| Encode.string message
             )
 
         RemoveTag i ->
             let
                 tag =
-                    .tag <| Maybe.withDefault { tag = "", webhook = "" } <| List.Extra.getAt i model.tags
+                    .tag <| Maybe.withDefault (Tag.new "" "") <| List.Extra.getAt i model.tags
             in
             ( model
-            , Requests.delete model (Tag <| Remove tag i) "tags/" i
+            , Requests.delete model (Tag <| Remove tag i) <| channelPath model <| "tags/" ++ String.fromInt i
             )
 
         UpdateTag i t ->
             ( model
-            , Requests.put model (Tag <| Update t i) "tags/" i <| Tag.toJson t
+            , Requests.put model (Tag <| Update t i) (channelPath model <| "tags/" ++ String.fromInt i) <| Tag.toJson t
+            )
+
+        EnableTag i enabled ->
+            let
+                tag =
+                    .tag <| Maybe.withDefault (Tag.new "" "") <| List.Extra.getAt i model.tags
+            in
+            ( model
+            , Requests.post model (Tag <| Enable tag enabled i) (channelPath model <| "tags/" ++ String.fromInt i ++ "/enabled") <| Tag.enabledJson enabled
             )
 
         UpdateSettings s ->
@@ -292,8 +370,8 @@ update msg model =
             )
 
         AddTag t ->
-            ( { model | newTag = { tag = "", webhook = "" } }
-            , Requests.post model (Tag <| Add t) "tags/add" <| Tag.toJson model.newTag
+            ( { model | newTag = Tag.new "" "" }
+            , Requests.post model (Tag <| Add t) (channelPath model "tags/add") <| Tag.toJson model.newTag
             )
 
         NoMsg ->
@@ -337,6 +415,122 @@ update msg model =
             , Cmd.none
             )
 
+        ActivationLoaded (Ok activated) ->
+            ( { model | activated = activated }
+            , Cmd.none
+            )
+
+        ActivationLoaded (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't load the activation: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        SetActivation activated ->
+            ( model
+            , Requests.post model (ActivationUpdated activated) (channelPath model "activation/") <| activationJson activated
+            )
+
+        ActivationUpdated activated (Ok _) ->
+            ( { model | activated = activated }
+            , Cmd.none
+            )
+
+        ActivationUpdated _ (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't change the activation: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        FilterLoaded (Ok f) ->
+            ( { model | filter = f }
+            , Cmd.none
+            )
+
+        FilterLoaded (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't load the filter: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        UpdateFilter f ->
+            ( { model | filter = f }
+            , Cmd.none
+            )
+
+        SaveFilter f ->
+            ( model
+            , Requests.post model FilterSaved "filter/" <| Filter.toJson f
+            )
+
+        FilterSaved (Ok _) ->
+            ( Alert.add model Alert.dismissableSuccess <|
+                "Filter sucessfully saved"
+            , Cmd.none
+            )
+
+        FilterSaved (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't save the filter: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        HistoryLoaded (Ok page) ->
+            ( { model | history = page }
+            , Cmd.none
+            )
+
+        HistoryLoaded (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't load the history: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        UpdateHistoryQuery q ->
+            ( { model | historyQuery = q }
+            , Cmd.none
+            )
+
+        LoadHistory q ->
+            ( { model | historyQuery = q }
+            , Requests.get model HistoryLoaded History.decode <| History.path model.channel q
+            )
+
+        Resend entryId ->
+            ( model
+            , Requests.post model Resent ("history/" ++ String.fromInt entryId ++ "/resend") Encode.null
+            )
+
+        Resent (Ok _) ->
+            ( Alert.add model Alert.dismissableSuccess <|
+                "Message queued for resending"
+            , Cmd.none
+            )
+
+        Resent (Err e) ->
+            ( Alert.add model Alert.dismissableAlert <|
+                "Can't resend the message: "
+                    ++ Error.toString e
+            , Cmd.none
+            )
+
+        SelectChannel c ->
+            let
+                q =
+                    model.historyQuery
+
+                newModel =
+                    { model | channel = c, historyQuery = { q | page = 0 } }
+            in
+            ( newModel
+            , loadChannel newModel
+            )
+
 
 updateSettings settings name value =
     case name of
@@ -371,7 +565,7 @@ tagListHead =
         ]
 
 
-tagActions i tv wv =
+tagActions i t =
     [ BG.buttonGroup []
         [ BG.button
             [ Button.primary
@@ -380,41 +574,60 @@ tagActions i tv wv =
             [ text "delete" ]
         , BG.button
             [ Button.primary
-            , Button.onClick <| UpdateTag i { tag = tv, webhook = wv }
+            , Button.onClick <| UpdateTag i t
             ]
             [ text "update" ]
+        , BG.button
+            [ if t.enabled then
+                Button.secondary
+
+              else
+                Button.warning
+            , Button.onClick <| EnableTag i (not t.enabled)
+            ]
+            [ if t.enabled then
+                text "disable"
+
+              else
+                text "enable"
+            ]
         ]
     ]
 
 
-tagRow i tid wid tv wv =
-    Table.tr []
+tagRow i tid wid t =
+    Table.tr
+        (if t.enabled then
+            []
+
+         else
+            [ Table.rowSecondary ]
+        )
         [ Table.td []
             [ Input.text
                 [ Input.id tid
-                , Input.value tv
+                , Input.value t.tag
                 , Input.onInput <| UpdateExistingTag i
                 ]
             ]
         , Table.td []
             [ Input.url
                 [ Input.id wid
-                , Input.value wv
+                , Input.value t.webhook
                 , Input.onInput <| UpdateExistingWebhook i
                 ]
             ]
         , Table.td []
             (tagActions
                 i
-                tv
-                wv
+                t
             )
         ]
 
 
 tagListBody model =
     Table.tbody []
-        (List.indexedMap (\i t -> tagRow i ("tag" ++ String.fromInt i) ("hook" ++ String.fromInt i) t.tag t.webhook) model.tags
+        (List.indexedMap (\i t -> tagRow i ("tag" ++ String.fromInt i) ("hook" ++ String.fromInt i) t) model.tags
             ++ [ Table.tr []
                     [ Table.td []
                         [ Input.text
@@ -539,6 +752,34 @@ settingsPanel model =
                     model.settings.reply
                 ]
             |> Fieldset.view
+        , Fieldset.config
+            |> Fieldset.asGroup
+            |> Fieldset.legend [] [ text "Activation:" ]
+            |> Fieldset.children
+                [ Html.p []
+                    [ text <|
+                        if model.activated then
+                            "The bot forwards the messages in the channel. "
+
+                        else
+                            "The bot ignores the messages in the channel (like #deactivate). "
+                    , Button.button
+                        [ if model.activated then
+                            Button.secondary
+
+                          else
+                            Button.warning
+                        , Button.onClick <| SetActivation (not model.activated)
+                        ]
+                        [ if model.activated then
+                            text "deactivate"
+
+                          else
+                            text "activate"
+                        ]
+                    ]
+                ]
+            |> Fieldset.view
         , Button.button
             [ if model.credentialsChanged then
                 Button.warning
@@ -556,6 +797,239 @@ settingsPanel model =
         ]
 
 
+listSection updater name fieldId values =
+    Form.group []
+        [ Form.label [ for fieldId ] [ text name ]
+        , Textarea.textarea
+            [ Textarea.id fieldId
+            , Textarea.rows 4
+            , Textarea.value <| String.join "\n" values
+            , Textarea.onInput <| updater << String.split "\n"
+            ]
+        ]
+
+
+numberSection updater name fieldId value =
+    Form.group []
+        [ Form.label [ for fieldId ] [ text name ]
+        , Input.number
+            [ Input.id fieldId
+            , Input.value <| String.fromInt value
+            , Input.onInput <| updater << Maybe.withDefault 0 << String.toInt
+            ]
+        ]
+
+
+selectSection updater name fieldId options value =
+    Form.group []
+        [ Form.label [ for fieldId ] [ text name ]
+        , Select.select
+            [ Select.id fieldId
+            , Select.onChange updater
+            ]
+            (List.map
+                (\o -> Select.item [ Html.Attributes.value o, selected <| o == value ] [ text o ])
+                options
+            )
+        ]
+
+
+filterPanel model =
+    let
+        f =
+            model.filter
+    in
+    Html.div []
+        [ Fieldset.config
+            |> Fieldset.asGroup
+            |> Fieldset.legend [] [ text "Blocked (one per line):" ]
+            |> Fieldset.children
+                [ listSection
+                    (\l -> UpdateFilter { f | blockedWords = l })
+                    "Words"
+                    "blocked_words"
+                    f.blockedWords
+                , listSection
+                    (\l -> UpdateFilter { f | blockedPatterns = l })
+                    "Patterns (glob, or regex between slashes)"
+                    "blocked_patterns"
+                    f.blockedPatterns
+                ]
+            |> Fieldset.view
+        , Fieldset.config
+            |> Fieldset.asGroup
+            |> Fieldset.legend [] [ text "Links:" ]
+            |> Fieldset.children
+                [ selectSection
+                    (\l -> UpdateFilter { f | links = l })
+                    "Links in messages"
+                    "links"
+                    Filter.linkPolicies
+                    f.links
+                , listSection
+                    (\l -> UpdateFilter { f | allowedDomains = l })
+                    "Allowed domains (one per line)"
+                    "allowed_domains"
+                    f.allowedDomains
+                ]
+            |> Fieldset.view
+        , Fieldset.config
+            |> Fieldset.asGroup
+            |> Fieldset.legend [] [ text "Length (0 means no limit):" ]
+            |> Fieldset.children
+                [ numberSection
+                    (\n -> UpdateFilter { f | minLength = n })
+                    "Minimum"
+                    "min_length"
+                    f.minLength
+                , numberSection
+                    (\n -> UpdateFilter { f | maxLength = n })
+                    "Maximum"
+                    "max_length"
+                    f.maxLength
+                ]
+            |> Fieldset.view
+        , Button.button
+            [ Button.primary
+            , Button.onClick <| SaveFilter f
+            ]
+            [ text "save" ]
+        ]
+
+
+historyQueryPanel q =
+    Fieldset.config
+        |> Fieldset.asGroup
+        |> Fieldset.legend [] [ text "Search:" ]
+        |> Fieldset.children
+            [ textInputSection
+                (\s -> UpdateHistoryQuery { q | search = s })
+                25
+                "Text"
+                q.search
+            , textInputSection
+                (\s -> UpdateHistoryQuery { q | tag = s })
+                25
+                "Tag"
+                q.tag
+            , textInputSection
+                (\s -> UpdateHistoryQuery { q | user = s })
+                25
+                "User"
+                q.user
+            , textInputSection
+                (\s -> UpdateHistoryQuery { q | since = s })
+                25
+                "Since (YYYY-MM-DD)"
+                q.since
+            , textInputSection
+                (\s -> UpdateHistoryQuery { q | until = s })
+                25
+                "Until (YYYY-MM-DD)"
+                q.until
+            , selectSection
+                (\s -> UpdateHistoryQuery { q | status = s })
+                "Status"
+                "status"
+                ("" :: History.statuses)
+                q.status
+            , Button.button
+                [ Button.primary
+                , Button.onClick <| LoadHistory { q | page = 0 }
+                ]
+                [ text "search" ]
+            ]
+        |> Fieldset.view
+
+
+historyListHead =
+    Table.simpleThead
+        [ Table.th [] [ text "Time" ]
+        , Table.th [] [ text "Channel" ]
+        , Table.th [] [ text "User" ]
+        , Table.th [] [ text "Tag" ]
+        , Table.th [] [ text "Status" ]
+        , Table.th [ Table.cellAttr <| style "width" "100%" ] [ text "Message" ]
+        , Table.th [] [ text "Action" ]
+        ]
+
+
+historyRow entry =
+    Table.tr
+        (if entry.status == "failed" then
+            [ Table.rowWarning ]
+
+         else
+            []
+        )
+        [ Table.td [] [ text <| History.formatTime entry.timestamp ]
+        , Table.td [] [ text entry.channel ]
+        , Table.td [] [ text entry.login ]
+        , Table.td [] [ text entry.tag ]
+        , Table.td [] [ text entry.status ]
+        , Table.td [] [ text entry.text ]
+        , Table.td []
+            (if entry.status == "failed" then
+                [ Button.button
+                    [ Button.primary
+                    , Button.small
+                    , Button.onClick <| Resend entry.id
+                    ]
+                    [ text "resend" ]
+                ]
+
+             else
+                []
+            )
+        ]
+
+
+historyPaging model =
+    let
+        page =
+            model.history
+
+        q =
+            model.historyQuery
+    in
+    Html.p []
+        [ BG.buttonGroup []
+            [ BG.button
+                [ Button.secondary
+                , Button.disabled <| page.page <= 0
+                , Button.onClick <| LoadHistory { q | page = page.page - 1 }
+                ]
+                [ text "previous" ]
+            , BG.button
+                [ Button.secondary
+                , Button.disabled <| page.page >= History.lastPage page
+                , Button.onClick <| LoadHistory { q | page = page.page + 1 }
+                ]
+                [ text "next" ]
+            ]
+        , text <|
+            " Page "
+                ++ String.fromInt (page.page + 1)
+                ++ " of "
+                ++ String.fromInt (History.lastPage page + 1)
+                ++ " ("
+                ++ String.fromInt page.total
+                ++ " entries)"
+        ]
+
+
+historyPanel model =
+    Html.div []
+        [ historyQueryPanel model.historyQuery
+        , historyPaging model
+        , Table.table
+            { options = [ Table.small, Table.responsive ]
+            , thead = historyListHead
+            , tbody = Table.tbody [] <| List.map historyRow model.history.entries
+            }
+        ]
+
+
 messageListHead =
     Table.simpleThead
         [ Table.th [ Table.cellAttr <| style "width" "15%" ] [ text "Type" ]
@@ -611,15 +1085,27 @@ tab name content =
         }
 
 
+-- only with more than one channel
+channelSelector model =
+    if List.length model.settings.channels > 1 then
+        selectSection SelectChannel "Channel" "channel" model.settings.channels model.channel
+
+    else
+        text ""
+
+
 mainPanel model =
     [ div
         [ style "padding" "10px" ]
-        [ Tab.config TabMsgMain
+        [ channelSelector model
+        , Tab.config TabMsgMain
             |> Tab.pills
             |> Tab.useHash True
             |> Tab.items
                 [ tab "Tags" <| tagPanel model
                 , tab "Settings" <| settingsPanel model
+                , tab "Filter" <| filterPanel model
+                , tab "History" <| historyPanel model
                 ]
             |> Tab.view model.tabState
         ]
@@ -10,10 +10,10 @@ get model msg decoder path =
         }
 
 
-delete model msg path name =
+delete model msg path =
     Http.request
         { method = "DELETE"
-        , url = model.base_url ++ path ++ String.fromInt name
+        , url = model.base_url ++ path
         , headers = []
         , body = Http.emptyBody
         , expect = Http.expectWhatever msg
@@ -22,10 +22,10 @@ delete model msg path name =
         }
 
 
-put model msg path name jsonValue =
+put model msg path jsonValue =
     Http.request
         { method = "PUT"
-        , url = model.base_url ++ path ++ String.fromInt name
+        , url = model.base_url ++ path
         , headers = []
         , body = Http.jsonBody <| jsonValue
         , expect = Http.expectWhatever msg
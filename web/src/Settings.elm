@@ -1,7 +1,7 @@
 module Settings exposing (..)
 
 import Json.Decode as Decode exposing (Decoder, bool, list, string)
-import Json.Decode.Pipeline exposing (required)
+import Json.Decode.Pipeline exposing (optional, required)
 import Json.Encode as Encode
 
 
@@ -12,6 +12,9 @@ type alias Settings =
     , messageSuccess : String
     , messageFailure : String
     , reply : Bool
+
+    -- read only, all channels of the bot
+    , channels : List String
     }
 
 
@@ -24,6 +27,7 @@ decode =
         |> Json.Decode.Pipeline.required "messageSuccess" Decode.string
         |> Json.Decode.Pipeline.required "messageFailure" Decode.string
         |> Json.Decode.Pipeline.required "reply" Decode.bool
+        |> Json.Decode.Pipeline.optional "channels" (Decode.list Decode.string) []
 
 
 toJson settings =
@@ -3,7 +3,7 @@ module Tag exposing (..)
 import Error
 import Http exposing (Error(..))
 import Json.Decode as Decode exposing (Decoder, list, string)
-import Json.Decode.Pipeline exposing (required)
+import Json.Decode.Pipeline exposing (optional, required)
 import Json.Encode as Encode
 import List.Extra exposing (removeAt, setAt, updateAt, updateIf)
 
@@ -11,6 +11,7 @@ import List.Extra exposing (removeAt, setAt, updateAt, updateIf)
 type alias Tag =
     { tag : String
     , webhook : String
+    , enabled : Bool
     }
 
 
@@ -24,21 +25,27 @@ decode =
     Decode.succeed Tag
         |> Json.Decode.Pipeline.required "tag" Decode.string
         |> Json.Decode.Pipeline.required "webhook" Decode.string
+        |> Json.Decode.Pipeline.optional "enabled" Decode.bool True
 
 
 toJson tag =
     Encode.object [ ( "tag", Encode.string tag.tag ), ( "webhook", Encode.string tag.webhook ) ]
 
 
+enabledJson enabled =
+    Encode.object [ ( "enabled", Encode.bool enabled ) ]
+
+
 new : String -> String -> Tag
 new ntag hook =
-    { tag = ntag, webhook = hook }
+    { tag = ntag, webhook = hook, enabled = True }
 
 
 type TagAction
     = Add Tag
     | Remove String Int
     | Update Tag Int
+    | Enable String Bool Int
 
 
 applyAction : TagAction -> List Tag -> List Tag
@@ -53,6 +60,9 @@ applyAction action ls =
         Update t i ->
             setAt i t ls
 
+        Enable _ enabled i ->
+            updateAt i (\t -> { t | enabled = enabled }) ls
+
 
 successMessage : TagAction -> String
 successMessage action =
@@ -66,6 +76,12 @@ successMessage action =
         Update t _ ->
             "Tag \"" ++ t.tag ++ "\" updated."
 
+        Enable name True _ ->
+            "Tag \"" ++ name ++ "\" enabled."
+
+        Enable name False _ ->
+            "Tag \"" ++ name ++ "\" disabled."
+
 
 failureMessage : TagAction -> Http.Error -> String
 failureMessage action e =
@@ -79,6 +95,12 @@ failureMessage action e =
 
                 Update t _ ->
                     "update Tag \"" ++ t.tag ++ "\""
+
+                Enable name True _ ->
+                    "enable Tag \"" ++ name ++ "\""
+
+                Enable name False _ ->
+                    "disable Tag \"" ++ name ++ "\""
            )
         ++ ": "
         ++ Error.toString e